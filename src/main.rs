@@ -9,26 +9,55 @@ use rocket::Data;
 use rocket::State;
 use rocket::Outcome;
 use rocket_contrib::json::Json;
-use rocket::response::NamedFile;
+use rocket::response::{Responder, Response};
 use rocket_contrib::serve::StaticFiles;
 use rocket_contrib::templates::Template;
-use rocket::request::{self, Form, FromRequest, Request};
+use rocket::request::{self, Form, FromQuery, FromRequest, LenientForm, Query, Request};
 use rocket::http::{Status, Cookie, Cookies, ContentType};
 
 use std::str;
+use std::io;
 use reqwest::Client;
 use clap::{App, Arg};
-use chrono::DateTime;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
 use std::io::SeekFrom;
 use std::error::Error;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::io::prelude::*;
+use std::os::unix::fs::{DirEntryExt, MetadataExt};
 use chrono::offset::Local;
 use grep::printer::Standard;
-use std::collections::HashMap;
+use regex::{Regex, RegexBuilder};
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::time::{Duration, Instant, SystemTime};
+use similar::{capture_diff_slices_deadline, group_diff_ops, Algorithm, DiffOp};
+use std::io::BufReader;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use rocket::response::Redirect;
-use grep::searcher::SearcherBuilder;
-use grep::regex::RegexMatcherBuilder;
+use rocket::response::Stream;
+use rocket::response::content::Content;
+use std::sync::mpsc::{channel, sync_channel, Receiver, RecvTimeoutError, SyncSender};
+use std::thread;
+use notify::{raw_watcher, RawEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use flate2::read::MultiGzDecoder;
+use zip::ZipArchive;
+use bzip2::read::MultiBzDecoder;
+use xz2::read::XzDecoder;
+use globset::{Glob, GlobMatcher, GlobSet, GlobSetBuilder};
+use ignore::{WalkBuilder, WalkState};
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::html::highlighted_html_for_string;
+use syntect::parsing::SyntaxSet;
+use pulldown_cmark::{html as markdown_html, Options as MarkdownOptions, Parser as MarkdownParser};
+use encoding_rs::{Encoding, GB18030, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
+use grep::searcher::{BinaryDetection, Searcher, SearcherBuilder, Sink, SinkContext, SinkContextKind, SinkMatch};
+use grep::regex::{RegexMatcher, RegexMatcherBuilder};
+use grep::matcher::{Match, Matcher, NoCaptures, NoError};
 use std::fs::{self, File, OpenOptions};
 use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE, COOKIE};
 
@@ -37,6 +66,19 @@ use multipart::server::save::SaveResult::*;
 
 static mut GLOBAL_ARGS: Option<Args> = None;
 
+// A line offset is recorded for every LINE_INDEX_INTERVAL lines
+const LINE_INDEX_INTERVAL: u64 = 1024;
+// How often the changed read cursors are saved.
+const CURSOR_SAVE_INTERVAL: Duration = Duration::from_secs(5);
+// The most line indexes kept, the least recently used one is dropped beyond it.
+const MAX_LINE_INDEXES: usize = 256;
+// The most files merged into a timeline.
+const MAX_TIMELINE_FILES: usize = 20;
+// Views index at most this many new bytes in place, larger files are indexed in the background.
+const LINE_INDEX_INLINE_LEN: u64 = 8 * 1024 * 1024;
+// The most files indexed in the background at once.
+const MAX_BACKGROUND_INDEXES: usize = 4;
+
 macro_rules! log {
     ($($x: expr), +) => {
         let mut str = Local::now().to_rfc2822();
@@ -61,6 +103,67 @@ struct Args {
     password: Option<String>,
     log: bool,
     write: bool,
+    encodings: Vec<(GlobMatcher, &'static Encoding)>,
+    time_formats: Vec<String>,
+    record_starts: Vec<Regex>,
+    file_types: Vec<(GlobMatcher, String)>,
+    levels: Vec<(String, Regex)>,
+    cursor_file: PathBuf,
+    search_threads: usize,
+    search_slots: Arc<Slots>,
+    // Each live tail holds a worker while it is open
+    stream_slots: Arc<Slots>,
+}
+
+// A number of slots shared by all requests, such as the files searched at the same time
+#[derive(Debug)]
+struct Slots {
+    size: usize,
+    used: Mutex<usize>,
+    freed: Condvar,
+}
+
+// A slot taken, it is given back when dropped
+#[derive(Debug)]
+struct Slot {
+    slots: Arc<Slots>,
+}
+
+impl Slots {
+    fn new(size: usize) -> Slots {
+        Slots {
+            size: std::cmp::max(size, 1),
+            used: Mutex::new(0),
+            freed: Condvar::new(),
+        }
+    }
+
+    // Waits until a slot is free
+    fn acquire(self: &Arc<Self>) -> Slot {
+        let mut used = self.used.lock().unwrap();
+        while *used >= self.size {
+            used = self.freed.wait(used).unwrap();
+        }
+        *used += 1;
+        Slot { slots: self.clone() }
+    }
+
+    // Takes a slot if one is free
+    fn try_acquire(self: &Arc<Self>) -> Option<Slot> {
+        let mut used = self.used.lock().unwrap();
+        if *used >= self.size {
+            return None;
+        }
+        *used += 1;
+        Some(Slot { slots: self.clone() })
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        *self.slots.used.lock().unwrap() -= 1;
+        self.slots.freed.notify_one();
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -114,13 +217,37 @@ impl IndexRender {
 }
 
 impl Args {
-    fn new(file_dir: PathBuf, username: Option<String>, password: Option<String>, log: bool, write: bool) -> Args {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        file_dir: PathBuf,
+        username: Option<String>,
+        password: Option<String>,
+        log: bool,
+        write: bool,
+        encodings: Vec<(GlobMatcher, &'static Encoding)>,
+        time_formats: Vec<String>,
+        record_starts: Vec<Regex>,
+        file_types: Vec<(GlobMatcher, String)>,
+        levels: Vec<(String, Regex)>,
+        cursor_file: PathBuf,
+        search_threads: usize,
+        max_streams: usize,
+    ) -> Args {
         Args {
             file_dir,
             username,
             password,
             log,
             write,
+            encodings,
+            time_formats,
+            record_starts,
+            file_types,
+            levels,
+            cursor_file,
+            search_threads,
+            search_slots: Arc::new(Slots::new(search_threads)),
+            stream_slots: Arc::new(Slots::new(max_streams)),
         }
     }
 }
@@ -131,6 +258,28 @@ struct DetailRender {
     seek: u64,
     file_path: String,
     write: bool,
+    inode: u64,
+    rotated: bool,
+    compressed: bool,
+    start_line: u64,
+    start_seek: u64,
+    encoding: String,
+    eof: bool,
+    jsonl: bool,
+    record_lines: Vec<usize>,
+    language: String,
+    // Source code is highlighted, anything else is tailed like a log
+    code: bool,
+    highlighted: bool,
+    preview: bool,
+    level_names: Vec<String>,
+    level_counts: Vec<usize>,
+    line_levels: Vec<String>,
+    level: String,
+    mark_first: u64,
+    mark_last: u64,
+    notice: String,
+    unread_line: u64,
 }
 
 impl DetailRender {
@@ -140,12 +289,111 @@ impl DetailRender {
             seek,
             file_path,
             write: false,
+            inode: 0,
+            rotated: false,
+            compressed: false,
+            start_line: 0,
+            start_seek: 0,
+            encoding: UTF_8.name().to_owned(),
+            eof: true,
+            jsonl: false,
+            record_lines: vec![],
+            language: "log".to_owned(),
+            code: false,
+            highlighted: false,
+            preview: false,
+            level_names: vec![],
+            level_counts: vec![],
+            line_levels: vec![],
+            level: "".to_owned(),
+            mark_first: 0,
+            mark_last: 0,
+            notice: "".to_owned(),
+            unread_line: 0,
         }
     }
 
     fn set_write(&mut self, write: bool) {
         self.write = write;
     }
+
+    fn set_inode(&mut self, inode: u64) {
+        self.inode = inode;
+    }
+
+    fn set_rotated(&mut self, rotated: bool) {
+        self.rotated = rotated;
+    }
+
+    fn set_compressed(&mut self, compressed: bool) {
+        self.compressed = compressed;
+    }
+
+    fn set_start_line(&mut self, start_line: u64) {
+        self.start_line = start_line;
+    }
+
+    fn set_start_seek(&mut self, start_seek: u64) {
+        self.start_seek = start_seek;
+    }
+
+    fn set_encoding(&mut self, encoding: &'static Encoding) {
+        self.encoding = encoding.name().to_owned();
+    }
+
+    fn set_eof(&mut self, eof: bool) {
+        self.eof = eof;
+    }
+
+    fn set_jsonl(&mut self, jsonl: bool) {
+        self.jsonl = jsonl;
+    }
+
+    fn set_record_lines(&mut self, record_lines: Vec<usize>) {
+        self.record_lines = record_lines;
+    }
+
+    fn set_language(&mut self, language: String) {
+        self.code = is_source_code(&language);
+        self.language = language;
+    }
+
+    // Replaces the content with the highlighted HTML
+    fn set_highlighted(&mut self, highlighted: String) {
+        self.content = highlighted;
+        self.highlighted = true;
+    }
+
+    fn set_preview(&mut self, preview: bool) {
+        self.preview = preview;
+    }
+
+    fn set_content(&mut self, content: String) {
+        self.content = content;
+    }
+
+    fn set_levels(&mut self, level_names: Vec<String>, level_counts: Vec<usize>, line_levels: Vec<String>) {
+        self.level_names = level_names;
+        self.level_counts = level_counts;
+        self.line_levels = line_levels;
+    }
+
+    fn set_level(&mut self, level: String) {
+        self.level = level;
+    }
+
+    fn set_marked_lines(&mut self, first: u64, last: u64) {
+        self.mark_first = first;
+        self.mark_last = last;
+    }
+
+    fn set_notice(&mut self, notice: String) {
+        self.notice = notice;
+    }
+
+    fn set_unread_line(&mut self, unread_line: u64) {
+        self.unread_line = unread_line;
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -164,269 +412,4570 @@ struct SearchRender {
     search: String,
     content: String,
     file_path: String,
+    json: bool,
 }
 
-impl SearchRender {
-    fn new(content: String, file_path: String, search: String) -> SearchRender {
-        SearchRender {
-            search,
-            content,
-            file_path,
-        }
-    }
+// A line in the search results, lines are counted from 1.
+// The offset is in bytes from the start of the file, it is only known for UTF-8 files.
+#[derive(Debug, Serialize)]
+struct SearchLine {
+    line: u64,
+    offset: Option<u64>,
+    text: String,
 }
 
-struct Authorization;
+// A matched line with its context lines, `spans` are the byte ranges of the matches in the text
+#[derive(Debug, Serialize)]
+struct SearchHit {
+    line: u64,
+    offset: Option<u64>,
+    text: String,
+    spans: Vec<(usize, usize)>,
+    before: Vec<SearchLine>,
+    after: Vec<SearchLine>,
+}
 
-#[derive(Debug)]
-enum AuthorizationError {
-    NoAuth,
+#[derive(Debug, Serialize)]
+struct SearchFile {
+    path: String,
+    hits: Vec<SearchHit>,
+    count: usize,
+    error: Option<String>,
 }
 
-impl<'a, 'r> FromRequest<'a, 'r> for Authorization {
-    type Error = AuthorizationError;
+// The query of the search page, the JSON and the streaming search.
+// The fields of the search form may be left empty, so they are parsed by `SearchOptions`.
+#[derive(FromForm, Debug)]
+struct SearchQuery {
+    search: String,
+    path: String,
+    before: Option<String>,
+    after: Option<String>,
+    case_sensitive: bool,
+    include: Option<String>,
+    exclude: Option<String>,
+    modified_within: Option<String>,
+    min_size: Option<String>,
+    max_size: Option<String>,
+    max_depth: Option<String>,
+    fixed_strings: bool,
+    word: bool,
+    invert: bool,
+    boolean: bool,
+    max_count: Option<String>,
+    mode: Option<String>,
+    binary: Option<String>,
+}
 
-    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
-        let config = request.guard::<State<Args>>().unwrap();
-        match &config.username {
-            Some(_username) => {
-                if !is_auth(&mut request.cookies(), &config) {
-                    return Outcome::Failure((Status::Forbidden, AuthorizationError::NoAuth));
-                }
-            }
-            _ => {}
-        }
+// What is given for a matched file: the matched lines, the number of them, or only the file
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SearchMode {
+    Lines,
+    Count,
+    Files,
+}
 
-        Outcome::Success(Authorization)
-    }
+// How to search the files, from the query of a search.
+// `boolean` takes the search as patterns combined with AND, OR and NOT, `max_count` is the most lines matched in a file.
+#[derive(Debug, Clone)]
+struct SearchOptions {
+    before: usize,
+    after: usize,
+    case_insensitive: bool,
+    fixed_strings: bool,
+    word: bool,
+    invert: bool,
+    boolean: bool,
+    max_count: Option<usize>,
+    mode: SearchMode,
+    skip_binary: bool,
+    threads: usize,
+    slots: Arc<Slots>,
+    filter: FileFilter,
 }
 
-fn directory_filter(dir: String) -> String {
-    unsafe {
-        let base_dir = GLOBAL_ARGS.clone().unwrap().file_dir;
-        dir.replace(base_dir.to_str().unwrap_or(""), "")
+impl SearchQuery {
+    // Whether a file filter or a search option is given, the form sends the default mode and binary handling anyway
+    fn has_options(&self) -> bool {
+        let values = [&self.include, &self.exclude, &self.modified_within, &self.min_size, &self.max_size, &self.max_depth, &self.max_count];
+        values.iter().any(|value| non_empty(value).is_some())
+            || self.fixed_strings
+            || self.word
+            || self.invert
+            || self.boolean
+            || non_empty(&self.mode).map_or(false, |mode| mode != "lines")
+            || non_empty(&self.binary).map_or(false, |binary| binary != "skip")
     }
 }
 
-// Gets a list of subfiles and directories in a directory
-fn get_directory_info_render(path: &PathBuf) -> Result<IndexRender, Box<dyn Error>> {
-    let render;
-    if path.is_dir() {
-        let mut elements: Vec<IndexElement> = vec![];
-
-        for entry in fs::read_dir(path)? {
-            let entry = entry?;
-            let file_path = entry.path();
-            let metadata = entry.metadata()?;
-            let file_name = file_path
-                .file_name()
-                .unwrap()
-                .to_string_lossy()
-                .to_string();
-
-            if file_name.get(0..1) == Some(".") {
-                continue;
-            }
-
-            let len = metadata.len();
-            let atime: DateTime<Local> = metadata.modified()?.into();
-            let atime_string = atime.format("%Y-%m-%d %T").to_string();
-
-            let class = match file_path.is_dir() {
-                true => "d".to_string(),
-                false => "f".to_string(),
-            };
-            elements.push(IndexElement::new(class, file_name, atime_string, len));
-        }
-        render = IndexRender::new(
-            true,
-            "".to_string(),
-            elements,
-            Some(directory_filter(path.to_string_lossy().to_string())),
-        );
-    } else {
-        render = IndexRender::new(false, "目录配置错误".to_string(), vec![], None);
+impl SearchOptions {
+    fn new(args: &Args, query: &SearchQuery) -> Result<SearchOptions, Box<dyn Error>> {
+        let mode = match non_empty(&query.mode).unwrap_or("lines") {
+            "lines" => SearchMode::Lines,
+            "count" => SearchMode::Count,
+            "files" => SearchMode::Files,
+            mode => return Err(format!("搜索方式错误：{}", mode).into()),
+        };
+        let skip_binary = match non_empty(&query.binary).unwrap_or("skip") {
+            "skip" => true,
+            "text" => false,
+            binary => return Err(format!("二进制文件的处理方式错误：{}", binary).into()),
+        };
+        // The context lines are only given with the matched lines
+        let context = |value: &Option<String>| match (mode, non_empty(value)) {
+            (SearchMode::Lines, Some(value)) => value.parse(),
+            _ => Ok(0),
+        };
+        Ok(SearchOptions {
+            before: context(&query.before)?,
+            after: context(&query.after)?,
+            case_insensitive: !query.case_sensitive,
+            fixed_strings: query.fixed_strings,
+            word: query.word,
+            invert: query.invert,
+            boolean: query.boolean,
+            max_count: non_empty(&query.max_count)
+                .map(|value| value.parse::<usize>())
+                .transpose()?
+                .filter(|count| *count > 0),
+            mode,
+            skip_binary,
+            threads: std::cmp::max(args.search_threads, 1),
+            slots: args.search_slots.clone(),
+            filter: FileFilter::new(query)?,
+        })
     }
-
-    Ok(render)
 }
 
-// Gets the content of a file 
-fn get_detail_render(path: &PathBuf, start_seek: u64) -> Result<DetailRender, Box<dyn Error>> {
-    let mut file = File::open(path)?;
-    let metadata = file.metadata()?;
-    let file_len = metadata.len();
-    let max_file_len = 512000;         // the max returned size, default is 512kb.
-    let mut contents = String::new();
-    let seek = file_len;
-    
-    if file_len > max_file_len || start_seek > 0 {
-        let read_start_seek: u64;
-        if start_seek > 0 {
-            read_start_seek = start_seek;
-        } else {
-            read_start_seek = file_len - max_file_len;
-        }
+// Which files of a directory are searched.
+// The globs are matched with the paths under the searched directory, `include` with the files only.
+#[derive(Debug, Clone, Default)]
+struct FileFilter {
+    include: Option<GlobSet>,
+    exclude: Option<GlobSet>,
+    modified_after: Option<SystemTime>,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    max_depth: Option<usize>,
+}
 
-        let (c, _) = attemp_to_read_file(&mut file, read_start_seek, 3)?;
-        contents = c;
-    } else {
-        file.read_to_string(&mut contents)?;
+impl FileFilter {
+    fn new(query: &SearchQuery) -> Result<FileFilter, Box<dyn Error>> {
+        let modified_after = match non_empty(&query.modified_within) {
+            Some(age) => SystemTime::now().checked_sub(parse_age(age)?),
+            None => None,
+        };
+        Ok(FileFilter {
+            include: non_empty(&query.include).map(parse_globs).transpose()?,
+            exclude: non_empty(&query.exclude).map(parse_globs).transpose()?,
+            modified_after,
+            min_size: non_empty(&query.min_size).map(parse_size).transpose()?,
+            max_size: non_empty(&query.max_size).map(parse_size).transpose()?,
+            max_depth: non_empty(&query.max_depth).map(|depth| depth.parse()).transpose()?,
+        })
     }
 
-    let render = DetailRender::new(contents, directory_filter(path.to_string_lossy().to_string()), seek);
-    Ok(render)
-}
+    fn includes(&self, relative: &Path) -> bool {
+        self.include.as_ref().map_or(true, |include| include.is_match(relative))
+    }
 
-// Try to read the file some times.
-fn attemp_to_read_file(
-    file: &mut File,
-    seek: u64,
-    times: u8,
-) -> Result<(String, u64), Box<dyn Error>> {
-    let mut buff = vec![];
-    file.seek(SeekFrom::Start(seek))?;
-    file.read_to_end(&mut buff)?;
-    match String::from_utf8(buff) {
-        Ok(s) => {
-            return Ok((s, seek));
-        }
-        Err(e) => {
-            if times > 0 {
-                // That returned content that intercepted by Seek maybe is incomplete(multibyte encoding),so sets some offset 
-                return attemp_to_read_file(file, seek + 1, times - 1);
-            } else {
-                return Err(Box::new(e));
-            }
-        }
+    fn excludes(&self, relative: &Path) -> bool {
+        self.exclude.as_ref().map_or(false, |exclude| exclude.is_match(relative))
     }
-}
 
-// Gets the filtered content of a file
-fn get_search_render(
-    path: &PathBuf,
-    search: &str,
-    before: &str,
-    after: &str,
-    case_insensitive: bool
-) -> Result<SearchRender, Box<dyn Error>> {
-    let single_page_limit = 10485760;   // The max size of filtered result per page
-    let mut size_limit = 0; // The max read size of all pages
-    let mut content = "".to_string();
-    let is_root = format!("{:?}", path).to_string() == "\"/home/smoothsea/project/108new/./application\"".to_string();
-    
-    if path.is_dir() {
-        for entry in fs::read_dir(path)?.filter(|en| {
-            !en.as_ref().unwrap().path().file_name().unwrap().to_str().unwrap().starts_with(".")
-        }) {
-            let entry = entry?;
-            let path = entry.path();
-            let ret = get_search_render(&path, search, before, after, case_insensitive);
+    fn allows_metadata(&self, metadata: &fs::Metadata) -> bool {
+        self.allows(metadata.modified().ok(), metadata.len())
+    }
 
-            match ret {
-                Ok(render) => {
-                    if render.content.len() > 0 {
-                        if path.is_dir() {
-                            content = format!("{}{}", content, render.content);
-                        } else {
-                            content = format!(
-                                "{}\r\n\r\n\r\n\r\n{}\r\n\r\n{}",
-                                content,
-                                directory_filter(path.to_str().unwrap().to_string()),
-                                render.content
-                            );
-                        }
-                    }
-                },
-                Err(e) => {
-                    content = format!(
-                        "{}\r\n\r\n\r\n\r\n{}\r\n\r\n{}",
-                        content,
-                        directory_filter(path.to_str().unwrap().to_string()),
-                        e.as_ref(),
-                    );
-                }
-            }
-        }
-    } else {
-        let file = File::open(path)?;
-        let mut matcher = RegexMatcherBuilder::new();
-        matcher.case_insensitive(case_insensitive);
-        let matcher = matcher.build(search)?;
-        let mut search_build = SearcherBuilder::new();
-        let mut printer = Standard::new_no_color(vec![]);
-        let before_num: usize = before.parse()?;
-        let after_num: usize = after.parse()?;
-        size_limit = single_page_limit;
-        search_build.multi_line(true);
-        search_build.after_context(after_num);
-        search_build.before_context(before_num);
-        search_build
-            .build()
-            .search_file(&matcher, &file, printer.sink(&matcher))?;
-        let search_bytes = printer.into_inner().into_inner();
-        content = String::from_utf8(search_bytes)?;
+    // The size of a member is the one before compression
+    fn allows_member(&self, entry: &ArchiveEntry) -> bool {
+        self.allows(entry.modified, entry.size)
+    }
 
-        if content.len() > size_limit {
-            return Err("搜索结果太大，请使用更准确的搜索词")?;
+    // An archive older than the files wanted is skipped without opening it, its members aren't newer.
+    // The sizes are checked with the members.
+    fn allows_archive(&self, metadata: &fs::Metadata) -> bool {
+        match (self.modified_after, metadata.modified()) {
+            (Some(after), Ok(modified)) => modified >= after,
+            _ => true,
         }
     }
 
-    Ok(SearchRender::new(
-        content,
-        path.to_string_lossy().to_string(),
-        search.to_string(),
-    ))
+    fn allows(&self, modified: Option<SystemTime>, len: u64) -> bool {
+        let modified = match (self.modified_after, modified) {
+            (Some(after), Some(modified)) => modified >= after,
+            _ => true,
+        };
+        modified
+            && self.min_size.map_or(true, |min| len >= min)
+            && self.max_size.map_or(true, |max| len <= max)
+    }
 }
 
-fn is_auth(cookies: &mut Cookies, config: &Args) -> bool {
-    let config = config.to_owned().clone();
-    let username = cookies
-        .get_private("username")
-        .map(|value| format!("{}", value));
-    config.username.map(|value| format!("username={}", value)) == username
+fn non_empty(value: &Option<String>) -> Option<&str> {
+    value.as_deref().map(|value| value.trim()).filter(|value| !value.is_empty())
 }
 
-#[get("/")]
-fn auth(args: State<Args>, mut cookies: Cookies) -> Redirect {
-    if is_auth(&mut cookies, &*args) {
-        Redirect::to("/file-reader-index")
-    } else {
-        Redirect::to("/login")
+// Globs separated by commas, such as `*.log, access/*`
+fn parse_globs(value: &str) -> Result<GlobSet, Box<dyn Error>> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in value.split(',').map(|glob| glob.trim()).filter(|glob| !glob.is_empty()) {
+        builder.add(Glob::new(glob)?);
     }
+    Ok(builder.build()?)
 }
 
-#[get("/file-reader-index")]
-fn index(args: State<Args>, _auth: Authorization) -> Template {
-    match get_directory_info_render(&args.file_dir) {
-        Ok(mut render) => {
-            if args.log {
-                log!("Access index");
-            }
-            render.set_write(args.write);
-            return Template::render("index", render);
-        }
-        Err(e) => {
-            let render = ErrorRender::new(e.to_string());
-            return Template::render("error", render);
-        }
+// Such as `30m`, `24h`, `7d` or `2w`
+fn parse_age(value: &str) -> Result<Duration, Box<dyn Error>> {
+    let value = value.to_lowercase();
+    let (number, unit) = value.split_at(value.trim_end_matches(char::is_alphabetic).len());
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" | "" => 3600,
+        "d" => 86400,
+        "w" => 604800,
+        _ => return Err(format!("时间格式错误：{}", value))?,
     };
+    let number: u64 = number.trim().parse().map_err(|_| format!("时间格式错误：{}", value))?;
+    let seconds = number.checked_mul(seconds).ok_or_else(|| format!("时间格式错误：{}", value))?;
+    Ok(Duration::from_secs(seconds))
 }
 
-#[get("/login")]
-fn login() -> Template {
-    Template::render("login", ErrorRender::new("".to_owned()))
+// A byte offset in decimal, or in hexadecimal with `0x` as the offsets of the hex view are shown
+fn parse_offset(value: &str) -> Result<u64, Box<dyn Error>> {
+    let value = value.trim();
+    let offset = match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    Ok(offset.map_err(|_| format!("偏移量格式错误：{}", value))?)
 }
 
-#[get("/debug")]
-fn debug(args: State<Args>) -> Template {
-    if args.log {
-        log!("Access debug");
-    }
-    Template::render("debug", ErrorRender::new("".to_owned()))
+// Such as `500`, `10k`, `20M` or `1G`
+fn parse_size(value: &str) -> Result<u64, Box<dyn Error>> {
+    let value = value.to_lowercase();
+    let value = value.trim_end_matches('b');
+    let (number, unit) = value.split_at(value.trim_end_matches(char::is_alphabetic).len());
+    let bytes = match unit {
+        "" => 1,
+        "k" => 1 << 10,
+        "m" => 1 << 20,
+        "g" => 1 << 30,
+        _ => return Err(format!("文件大小格式错误：{}", value))?,
+    };
+    let number: u64 = number.trim().parse().map_err(|_| format!("文件大小格式错误：{}", value))?;
+    Ok(number.checked_mul(bytes).ok_or_else(|| format!("文件大小格式错误：{}", value))?)
 }
 
-#[post("/login", data = "<login>")]
+// How far a search has gone, `files` is the number of files to search
+#[derive(Debug, Serialize, Clone, Default)]
+struct SearchProgress {
+    files: usize,
+    scanned: usize,
+    matches: usize,
+    binary: usize,
+    truncated: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct SearchFileEvent<'a> {
+    file: &'a SearchFile,
+    progress: &'a SearchProgress,
+}
+
+#[derive(Debug, Serialize)]
+struct SearchJsonRender {
+    search: String,
+    files: Vec<SearchFile>,
+    truncated: bool,
+}
+
+// A match found in a file, the offset is in bytes from the start of the file, lines are counted from 1.
+// `index` is the place of the match in the file, from 1.
+#[derive(Debug, Serialize)]
+struct FindMatch {
+    index: u64,
+    offset: u64,
+    length: u64,
+    line: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct FindRender {
+    file_path: String,
+    search: String,
+    matches: Vec<FindMatch>,
+    total: Option<u64>,
+}
+
+impl FindRender {
+    fn new(file_path: String, search: String, matches: Vec<FindMatch>, total: Option<u64>) -> FindRender {
+        FindRender {
+            file_path,
+            search,
+            matches,
+            total,
+        }
+    }
+}
+
+impl SearchRender {
+    fn new(content: String, file_path: String, search: String) -> SearchRender {
+        SearchRender {
+            search,
+            content,
+            file_path,
+            json: false,
+        }
+    }
+
+    // The results are fetched from the JSON search by the page
+    fn set_json(&mut self, json: bool) {
+        self.json = json;
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct LinesRender {
+    content: String,
+    file_path: String,
+    start_line: u64,
+    end_line: u64,
+    total_lines: u64,
+    seek: u64,
+}
+
+impl LinesRender {
+    fn new(content: String, file_path: String, start_line: u64, end_line: u64, total_lines: u64, seek: u64) -> LinesRender {
+        LinesRender {
+            content,
+            file_path,
+            start_line,
+            end_line,
+            total_lines,
+            seek,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct HexRow {
+    offset: String,
+    hex: String,
+    ascii: String,
+}
+
+impl HexRow {
+    fn new(offset: u64, bytes: &[u8]) -> HexRow {
+        let mut hex = String::new();
+        for (i, b) in bytes.iter().enumerate() {
+            if i == 8 {
+                hex.push(' ');
+            }
+            hex.push_str(&format!("{:02x} ", b));
+        }
+        let ascii = bytes
+            .iter()
+            .map(|b| match *b {
+                0x20..=0x7e => *b as char,
+                _ => '.',
+            })
+            .collect();
+
+        HexRow {
+            offset: format!("{:08x}", offset),
+            hex,
+            ascii,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct HexRender {
+    file_path: String,
+    seek: u64,
+    file_len: Option<u64>,
+    rows: Vec<HexRow>,
+    has_previous: bool,
+    has_next: bool,
+    previous_seek: u64,
+    next_seek: u64,
+}
+
+impl HexRender {
+    fn new(file_path: String, seek: u64, file_len: Option<u64>, rows: Vec<HexRow>, page_size: u64, has_next: bool) -> HexRender {
+        HexRender {
+            file_path,
+            seek,
+            file_len,
+            rows,
+            has_previous: seek > 0,
+            has_next,
+            previous_seek: seek.saturating_sub(page_size),
+            next_seek: seek.saturating_add(page_size),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct TimelineEntry {
+    source: usize,
+    time: String,
+    content: String,
+    #[serde(skip)]
+    timestamp: Option<NaiveDateTime>,
+}
+
+impl TimelineEntry {
+    fn new(source: usize, timestamp: Option<NaiveDateTime>, content: String) -> TimelineEntry {
+        let time = timestamp
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S%.3f").to_string())
+            .unwrap_or_default();
+        TimelineEntry {
+            source,
+            time,
+            content,
+            timestamp,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct TimelineSource {
+    file_path: String,
+    seek: u64,
+    inode: u64,
+    compressed: bool,
+}
+
+impl TimelineSource {
+    fn new(file_path: String, seek: u64, inode: u64) -> TimelineSource {
+        TimelineSource {
+            file_path,
+            seek,
+            inode,
+            compressed: false,
+        }
+    }
+
+    fn set_compressed(&mut self, compressed: bool) {
+        self.compressed = compressed;
+    }
+}
+
+// The files of a timeline as repeated `path` parameters, with the `seek` and `inode` of each for the live tail.
+// The paths aren't joined, they may contain commas.
+#[derive(Debug, Default)]
+struct TimelineQuery {
+    paths: Vec<String>,
+    seeks: Vec<u64>,
+    inodes: Vec<u64>,
+}
+
+impl<'q> FromQuery<'q> for TimelineQuery {
+    type Error = String;
+
+    fn from_query(query: Query<'q>) -> Result<TimelineQuery, String> {
+        let mut timeline = TimelineQuery::default();
+        for item in query {
+            let (key, value) = item.key_value_decoded();
+            match key.as_str() {
+                "path" => timeline.paths.push(value),
+                "seek" => timeline.seeks.push(value.parse().unwrap_or(0)),
+                "inode" => timeline.inodes.push(value.parse().unwrap_or(0)),
+                _ => {}
+            }
+        }
+        Ok(timeline)
+    }
+}
+
+// The files of a timeline page as repeated `file` parameters
+#[derive(Debug, Default)]
+struct TimelineFiles(Vec<String>);
+
+impl<'q> FromQuery<'q> for TimelineFiles {
+    type Error = String;
+
+    fn from_query(query: Query<'q>) -> Result<TimelineFiles, String> {
+        let files = query
+            .map(|item| item.key_value_decoded())
+            .filter(|(key, _)| key == "file")
+            .map(|(_, value)| value)
+            .collect();
+        Ok(TimelineFiles(files))
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct TimelineRender {
+    sources: Vec<TimelineSource>,
+    entries: Vec<TimelineEntry>,
+}
+
+impl TimelineRender {
+    fn new(sources: Vec<TimelineSource>, entries: Vec<TimelineEntry>) -> TimelineRender {
+        TimelineRender { sources, entries }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct JsonRecord {
+    fields: Option<Value>,
+    // The line as is, if it isn't a JSON object
+    raw: Option<String>,
+}
+
+impl JsonRecord {
+    fn parse(line: &str) -> JsonRecord {
+        match serde_json::from_str::<Value>(line) {
+            Ok(value) if value.is_object() => JsonRecord {
+                fields: Some(value),
+                raw: None,
+            },
+            _ => JsonRecord {
+                fields: None,
+                raw: Some(line.to_owned()),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct JsonlRender {
+    file_path: String,
+    records: Vec<JsonRecord>,
+    columns: Vec<String>,
+    seek: u64,
+    inode: u64,
+    rotated: bool,
+}
+
+impl JsonlRender {
+    fn new(file_path: String, records: Vec<JsonRecord>, seek: u64, inode: u64) -> JsonlRender {
+        // The union of the top level keys, in the order they first appear
+        let mut columns: Vec<String> = vec![];
+        for object in records.iter().filter_map(|r| r.fields.as_ref().and_then(|f| f.as_object())) {
+            for key in object.keys() {
+                if !columns.contains(key) {
+                    columns.push(key.clone());
+                }
+            }
+        }
+
+        JsonlRender {
+            file_path,
+            records,
+            columns,
+            seek,
+            inode,
+            rotated: false,
+        }
+    }
+
+    fn set_rotated(&mut self, rotated: bool) {
+        self.rotated = rotated;
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct JsonlPage {
+    file_path: String,
+    filter: String,
+    compressed: bool,
+    data: String,
+}
+
+impl JsonlPage {
+    fn new(file_path: String, filter: String, compressed: bool, data: String) -> JsonlPage {
+        JsonlPage {
+            file_path,
+            filter,
+            compressed,
+            // The data is embedded in a script tag, `<` is escaped so neither `</script>` nor `<!--` can end or change it
+            data: data.replace('<', "\\u003c"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct PreviewRender {
+    file_path: String,
+    markdown: bool,
+    table: bool,
+    tree: bool,
+    html: String,
+    json: String,
+    header: Vec<String>,
+    rows: Vec<Vec<String>>,
+    total_rows: usize,
+    page: usize,
+    pages: usize,
+    sort: Option<usize>,
+    desc: bool,
+}
+
+impl PreviewRender {
+    fn new(file_path: String) -> PreviewRender {
+        PreviewRender {
+            file_path,
+            markdown: false,
+            table: false,
+            tree: false,
+            html: String::new(),
+            json: String::new(),
+            header: vec![],
+            rows: vec![],
+            total_rows: 0,
+            page: 1,
+            pages: 1,
+            sort: None,
+            desc: false,
+        }
+    }
+
+    fn set_markdown(&mut self, html: String) {
+        self.markdown = true;
+        self.html = html;
+    }
+
+    fn set_tree(&mut self, json: String) {
+        self.tree = true;
+        self.json = json;
+    }
+
+    fn set_table(&mut self, header: Vec<String>, rows: Vec<Vec<String>>, total_rows: usize, page: usize, pages: usize) {
+        self.table = true;
+        self.header = header;
+        self.rows = rows;
+        self.total_rows = total_rows;
+        self.page = page;
+        self.pages = pages;
+    }
+
+    fn set_sort(&mut self, sort: Option<usize>, desc: bool) {
+        self.sort = sort;
+        self.desc = desc;
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct DiffRow {
+    class: String,
+    sign: String,
+    left_no: Option<usize>,
+    left: Option<String>,
+    right_no: Option<usize>,
+    right: Option<String>,
+}
+
+impl DiffRow {
+    fn new(class: &str, sign: &str, left: Option<(usize, &str)>, right: Option<(usize, &str)>) -> DiffRow {
+        DiffRow {
+            class: class.to_owned(),
+            sign: sign.to_owned(),
+            left_no: left.map(|(no, _)| no),
+            left: left.map(|(_, text)| text.to_owned()),
+            right_no: right.map(|(no, _)| no),
+            right: right.map(|(_, text)| text.to_owned()),
+        }
+    }
+
+    // The header of a hunk, such as `@@ -10,7 +10,8 @@`
+    fn hunk(text: String) -> DiffRow {
+        DiffRow {
+            class: "hunk".to_owned(),
+            sign: "".to_owned(),
+            left_no: None,
+            left: Some(text.clone()),
+            right_no: None,
+            right: Some(text),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct DiffRender {
+    left_path: String,
+    left_range: String,
+    right_path: String,
+    right_range: String,
+    side_by_side: bool,
+    ignore_time: bool,
+    identical: bool,
+    rows: Vec<DiffRow>,
+}
+
+impl DiffRender {
+    fn new(left_path: String, left_range: String, right_path: String, right_range: String, rows: Vec<DiffRow>) -> DiffRender {
+        DiffRender {
+            left_path,
+            left_range,
+            right_path,
+            right_range,
+            side_by_side: false,
+            ignore_time: false,
+            identical: rows.is_empty(),
+            rows,
+        }
+    }
+
+    fn set_side_by_side(&mut self, side_by_side: bool) {
+        self.side_by_side = side_by_side;
+    }
+
+    fn set_ignore_time(&mut self, ignore_time: bool) {
+        self.ignore_time = ignore_time;
+    }
+}
+
+// The part of a file compared
+#[derive(Debug)]
+enum DiffRange {
+    Whole,
+    // The first and the last line, from 1
+    Lines(usize, usize),
+    // The first and the last byte
+    Bytes(u64, u64),
+}
+
+impl DiffRange {
+    // Such as `lines:10-200` or `bytes:0-4096`
+    fn parse(range: &str) -> Result<DiffRange, Box<dyn Error>> {
+        let range = range.trim();
+        if range.is_empty() {
+            return Ok(DiffRange::Whole);
+        }
+        let (unit, span) = match range.find(':') {
+            Some(pos) => (&range[..pos], &range[pos + 1..]),
+            None => ("lines", range),
+        };
+        let (first, last) = match span.find('-') {
+            Some(pos) => (span[..pos].trim(), span[pos + 1..].trim()),
+            None => return Err(format!("范围格式错误：{}", range).into()),
+        };
+        let (first, last): (u64, u64) = (first.parse()?, last.parse()?);
+        if first > last {
+            return Err(format!("范围的起点大于终点：{}", range).into());
+        }
+        match unit {
+            "lines" if first == 0 => Err(format!("行号从 1 开始：{}", range).into()),
+            "lines" => Ok(DiffRange::Lines(first as usize, last as usize)),
+            "bytes" => Ok(DiffRange::Bytes(first, last)),
+            _ => Err(format!("范围格式错误：{}", range).into()),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum LineRange {
+    Head(u64),
+    Tail(u64),
+    Range(u64, u64),
+}
+
+// Sparse index of line offsets in a file, lines are counted from 0.
+#[derive(Debug)]
+struct LineIndex {
+    inode: u64,
+    encoding: &'static Encoding,
+    indexed_len: u64,
+    lines: u64,
+    offsets: Vec<u64>,
+}
+
+impl LineIndex {
+    fn new(inode: u64, encoding: &'static Encoding) -> LineIndex {
+        LineIndex {
+            inode,
+            encoding,
+            indexed_len: 0,
+            lines: 0,
+            offsets: vec![0],
+        }
+    }
+
+    // Starts over if the file has been rotated or truncated.
+    fn reset_if_replaced(&mut self, metadata: &fs::Metadata) {
+        if self.inode != metadata.ino() || self.indexed_len > metadata.len() {
+            *self = LineIndex::new(metadata.ino(), self.encoding);
+        }
+    }
+
+    // Indexes the complete lines appended since the last update.
+    fn update(&mut self, file: &mut File, file_len: u64) -> io::Result<()> {
+        file.seek(SeekFrom::Start(self.indexed_len))?;
+        let mut reader = BufReader::with_capacity(65536, Read::by_ref(file).take(file_len - self.indexed_len));
+        let mut offset = self.indexed_len;
+        let newline = newline_unit(self.encoding);
+        // The first byte of a UTF-16 newline has been read at the even offset before
+        let mut pending = false;
+        loop {
+            let len = {
+                let buff = reader.fill_buf()?;
+                for (i, b) in buff.iter().enumerate() {
+                    let ends = match newline {
+                        Some(unit) if (offset + i as u64) % 2 == 0 => {
+                            pending = *b == unit[0];
+                            false
+                        }
+                        Some(unit) => pending && *b == unit[1],
+                        None => *b == b'\n',
+                    };
+                    if ends {
+                        self.lines += 1;
+                        self.indexed_len = offset + i as u64 + 1;
+                        if self.lines % LINE_INDEX_INTERVAL == 0 {
+                            self.offsets.push(self.indexed_len);
+                        }
+                    }
+                }
+                buff.len()
+            };
+            if len == 0 {
+                break;
+            }
+            offset += len as u64;
+            reader.consume(len);
+        }
+        Ok(())
+    }
+
+    // The total number of lines, including an incomplete last line.
+    fn total_lines(&self, file_len: u64) -> u64 {
+        match file_len > self.indexed_len {
+            true => self.lines + 1,
+            false => self.lines,
+        }
+    }
+
+    // Gets the offset where a line begins, scanning forward from the nearest indexed line.
+    fn offset_of_line(&self, file: &mut File, line: u64) -> io::Result<u64> {
+        let nearest = std::cmp::min(line / LINE_INDEX_INTERVAL, self.offsets.len() as u64 - 1);
+        let mut offset = self.offsets[nearest as usize];
+        let mut current = nearest * LINE_INDEX_INTERVAL;
+
+        file.seek(SeekFrom::Start(offset))?;
+        let mut reader = BufReader::new(Read::by_ref(file));
+        let mut buff = vec![];
+        while current < line {
+            buff.clear();
+            let len = read_line(&mut reader, self.encoding, &mut buff)?;
+            if len == 0 {
+                break;
+            }
+            offset += len as u64;
+            current += 1;
+        }
+        Ok(offset)
+    }
+
+    // Gets the line that the byte at `offset` belongs to.
+    fn line_of_offset(&self, file: &mut File, offset: u64) -> io::Result<u64> {
+        let nearest = match self.offsets.binary_search(&offset) {
+            Ok(i) => i,
+            Err(i) => i - 1,
+        };
+        let start = self.offsets[nearest];
+        let mut buff = vec![];
+        file.seek(SeekFrom::Start(start))?;
+        Read::by_ref(file).take(offset - start).read_to_end(&mut buff)?;
+        let lines = line_ends(&buff, start, self.encoding).count() as u64;
+        Ok(nearest as u64 * LINE_INDEX_INTERVAL + lines)
+    }
+}
+
+// Highlights source code on the server, highlight.js in the browser chokes on large files.
+struct Highlighter {
+    syntaxes: SyntaxSet,
+    theme: Theme,
+}
+
+impl Highlighter {
+    fn new() -> Highlighter {
+        let mut themes = ThemeSet::load_defaults();
+        Highlighter {
+            syntaxes: SyntaxSet::load_defaults_newlines(),
+            theme: themes.themes.remove("InspiredGitHub").expect("缺少高亮主题"),
+        }
+    }
+
+    fn highlight(&self, content: &str, language: &str) -> String {
+        let syntax = self
+            .syntaxes
+            .find_syntax_by_token(language)
+            .unwrap_or_else(|| self.syntaxes.find_syntax_plain_text());
+        highlighted_html_for_string(content, &self.syntaxes, syntax, &self.theme)
+    }
+}
+
+// The line indexes of files with when they were last used, built on the first read by lines and updated as the files grow.
+struct LineIndexes {
+    indexes: Mutex<HashMap<PathBuf, (Instant, Arc<Mutex<LineIndex>>)>>,
+    // The files being indexed in the background
+    indexing: Arc<Mutex<HashSet<PathBuf>>>,
+}
+
+impl LineIndexes {
+    fn new() -> LineIndexes {
+        LineIndexes {
+            indexes: Mutex::new(HashMap::new()),
+            indexing: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    // Gets the index of a file, dropping the least recently used one when there are too many.
+    fn index_of(&self, path: &PathBuf, inode: u64) -> Arc<Mutex<LineIndex>> {
+        let mut indexes = self.indexes.lock().unwrap();
+        if !indexes.contains_key(path) && indexes.len() >= MAX_LINE_INDEXES {
+            let oldest = indexes.iter().min_by_key(|(_, (used, _))| *used).map(|(path, _)| path.clone());
+            if let Some(oldest) = oldest {
+                indexes.remove(&oldest);
+            }
+        }
+        let entry = indexes
+            .entry(path.clone())
+            .or_insert_with(|| (Instant::now(), Arc::new(Mutex::new(LineIndex::new(inode, detect_encoding(path))))));
+        entry.0 = Instant::now();
+        entry.1.clone()
+    }
+
+    // Calls `f` with the up to date index of a file, the index is rebuilt if the file has been rotated or truncated.
+    // A large unindexed part isn't indexed on the request, the file is indexed in the background to be asked again.
+    fn with_index<T, F>(&self, path: &PathBuf, f: F) -> Result<T, Box<dyn Error>>
+    where
+        F: FnOnce(&LineIndex, &mut File, u64) -> io::Result<T>,
+    {
+        if is_compressed(path) {
+            return Err("压缩文件不支持按行读取")?;
+        }
+        Ok(self.with_ready_index(path, f)?.ok_or("文件正在建立行索引，请稍后再试")?)
+    }
+
+    // The same, without the index if it isn't ready
+    fn with_ready_index<T, F>(&self, path: &PathBuf, f: F) -> Result<Option<T>, Box<dyn Error>>
+    where
+        F: FnOnce(&LineIndex, &mut File, u64) -> io::Result<T>,
+    {
+        let mut file = File::open(path)?;
+        let metadata = file.metadata()?;
+        let index = self.index_of(path, metadata.ino());
+        if self.indexing.lock().unwrap().contains(path) {
+            return Ok(None);
+        }
+        let mut guard = index.lock().unwrap();
+        guard.reset_if_replaced(&metadata);
+        if metadata.len() - guard.indexed_len > LINE_INDEX_INLINE_LEN {
+            drop(guard);
+            self.index_in_background(path, index);
+            return Ok(None);
+        }
+        guard.update(&mut file, metadata.len())?;
+        Ok(Some(f(&*guard, &mut file, metadata.len())?))
+    }
+
+    // Indexes a file on a thread of its own, unless it is being indexed already or too many files are.
+    fn index_in_background(&self, path: &PathBuf, index: Arc<Mutex<LineIndex>>) {
+        let mut indexing = self.indexing.lock().unwrap();
+        if indexing.len() >= MAX_BACKGROUND_INDEXES || !indexing.insert(path.clone()) {
+            return;
+        }
+        let (path, indexing) = (path.clone(), self.indexing.clone());
+        thread::spawn(move || {
+            if let Ok(mut file) = File::open(&path) {
+                if let Ok(metadata) = file.metadata() {
+                    let mut index = index.lock().unwrap();
+                    index.reset_if_replaced(&metadata);
+                    let _ = index.update(&mut file, metadata.len());
+                }
+            }
+            indexing.lock().unwrap().remove(&path);
+        });
+    }
+
+    fn line_of_offset(&self, path: &PathBuf, offset: u64) -> Result<u64, Box<dyn Error>> {
+        self.with_index(path, |index, file, _| index.line_of_offset(file, offset))
+    }
+
+    // Gets the line of `offset` for numbering a view without waiting on a large unindexed file,
+    // which is indexed in the background instead and shown without line numbers until then.
+    fn line_of_offset_if_ready(&self, path: &PathBuf, offset: u64) -> Option<u64> {
+        if is_compressed(path) {
+            return None;
+        }
+        self.with_ready_index(path, |index, file, _| index.line_of_offset(file, offset)).ok().flatten()
+    }
+}
+
+// How far a reader has viewed a file
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+struct ReadCursor {
+    inode: u64,
+    offset: u64,
+}
+
+// The read cursors of every reader by file, saved to a file to be kept across restarts.
+// The changes are saved every few seconds by a background thread, not on every update.
+struct ReadCursors {
+    cursors: Arc<Mutex<HashMap<String, HashMap<String, ReadCursor>>>>,
+    changed: Arc<AtomicBool>,
+}
+
+impl ReadCursors {
+    fn load(file: &PathBuf) -> ReadCursors {
+        let cursors = fs::read(file)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        let cursors = Arc::new(Mutex::new(cursors));
+        let changed = Arc::new(AtomicBool::new(false));
+
+        let (file, saved, unsaved) = (file.clone(), cursors.clone(), changed.clone());
+        thread::spawn(move || loop {
+            thread::sleep(CURSOR_SAVE_INTERVAL);
+            if unsaved.swap(false, AtomicOrdering::Relaxed) {
+                if let Err(e) = save_cursors(&file, &saved) {
+                    unsaved.store(true, AtomicOrdering::Relaxed);
+                    log!(format!("Save read cursors failed: {}", e));
+                }
+            }
+        });
+        ReadCursors { cursors, changed }
+    }
+
+    fn get(&self, reader: &str, path: &str) -> Option<ReadCursor> {
+        self.cursors.lock().unwrap().get(reader)?.get(path).copied()
+    }
+
+    // Moves the cursor forward, the cursor of a file that has been rotated since is replaced
+    fn update(&self, reader: &str, path: &str, cursor: ReadCursor) {
+        let mut cursors = self.cursors.lock().unwrap();
+        let files = cursors.entry(reader.to_owned()).or_default();
+        if let Some(old) = files.get(path) {
+            if old.inode == cursor.inode && old.offset >= cursor.offset {
+                return;
+            }
+        }
+        files.insert(path.to_owned(), cursor);
+        self.changed.store(true, AtomicOrdering::Relaxed);
+    }
+}
+
+// Written to a temporary file first, a crash while writing doesn't lose the cursors
+fn save_cursors(file: &PathBuf, cursors: &Mutex<HashMap<String, HashMap<String, ReadCursor>>>) -> io::Result<()> {
+    let data = serde_json::to_vec(&*cursors.lock().unwrap())?;
+    let temp = file.with_extension("tmp");
+    fs::write(&temp, data)?;
+    fs::rename(&temp, file)
+}
+
+// The key of a file in the read cursors, the same file may be given as `a/b` or `/a/b`
+fn cursor_key(path: &PathBuf) -> String {
+    path_to_relative(path)
+        .components()
+        .filter(|component| *component != std::path::Component::CurDir)
+        .collect::<PathBuf>()
+        .to_string_lossy()
+        .to_string()
+}
+
+// The logged in user, none when the authorization is disabled
+struct Authorization(Option<String>);
+
+#[derive(Debug)]
+enum AuthorizationError {
+    NoAuth,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Authorization {
+    type Error = AuthorizationError;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let config = request.guard::<State<Args>>().unwrap();
+        match &config.username {
+            Some(username) => {
+                if !is_auth(&mut request.cookies(), &config) {
+                    return Outcome::Failure((Status::Forbidden, AuthorizationError::NoAuth));
+                }
+                Outcome::Success(Authorization(Some(username.clone())))
+            }
+            _ => Outcome::Success(Authorization(None)),
+        }
+    }
+}
+
+// The conditional and range headers of a request
+struct RequestConditions {
+    range: Option<String>,
+    if_range: Option<String>,
+    if_none_match: Option<String>,
+    if_modified_since: Option<String>,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for RequestConditions {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let header = |name: &str| request.headers().get_one(name).map(|value| value.to_owned());
+        Outcome::Success(RequestConditions {
+            range: header("Range"),
+            if_range: header("If-Range"),
+            if_none_match: header("If-None-Match"),
+            if_modified_since: header("If-Modified-Since"),
+        })
+    }
+}
+
+// Who is reading, the read cursors are kept per reader.
+// It is the logged in user when the authorization is enabled, so that the cursors follow the user across browsers,
+// otherwise the browser.
+struct Reader(String);
+
+impl<'a, 'r> FromRequest<'a, 'r> for Reader {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        if let Outcome::Success(Authorization(Some(username))) = request.guard::<Authorization>() {
+            return Outcome::Success(Reader(format!("user:{}", username)));
+        }
+        let mut cookies = request.cookies();
+        let browser = match cookies.get("reader") {
+            Some(browser) => browser.value().to_owned(),
+            None => {
+                let mut hasher = DefaultHasher::new();
+                SystemTime::now().hash(&mut hasher);
+                request.client_ip().hash(&mut hasher);
+                let id = format!("{:x}", hasher.finish());
+                cookies.add(Cookie::build("reader", id.clone()).path("/").permanent().finish());
+                id
+            }
+        };
+        Outcome::Success(Reader(format!("browser:{}", browser)))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum ByteRange {
+    Whole,
+    // The first and the last byte
+    Part(u64, u64),
+    Unsatisfiable,
+}
+
+impl RequestConditions {
+    // Whether the copy of the client is still valid
+    fn not_modified(&self, etag: &str, modified: Option<&DateTime<Utc>>) -> bool {
+        if let Some(if_none_match) = &self.if_none_match {
+            return if_none_match
+                .split(',')
+                .map(|tag| tag.trim().trim_start_matches("W/"))
+                .any(|tag| tag == "*" || tag == etag);
+        }
+        match (&self.if_modified_since, modified) {
+            (Some(since), Some(modified)) => DateTime::parse_from_rfc2822(since)
+                .map_or(false, |since| modified.timestamp() <= since.timestamp()),
+            _ => false,
+        }
+    }
+
+    // The requested range of a file of `len` bytes.
+    // The range is ignored if the file has changed since `If-Range`, or if it has several parts.
+    fn byte_range(&self, len: u64, etag: &str, last_modified: &str) -> ByteRange {
+        let range = match &self.range {
+            Some(range) => range.trim(),
+            None => return ByteRange::Whole,
+        };
+        if let Some(if_range) = &self.if_range {
+            if if_range != etag && if_range != last_modified {
+                return ByteRange::Whole;
+            }
+        }
+        let spec = match range.strip_prefix("bytes=") {
+            Some(spec) if !spec.contains(',') => spec.trim(),
+            _ => return ByteRange::Whole,
+        };
+
+        let (first, last) = match spec.find('-') {
+            Some(pos) => (&spec[..pos], &spec[pos + 1..]),
+            None => return ByteRange::Whole,
+        };
+        let (start, end) = match (first.parse::<u64>(), last.parse::<u64>()) {
+            (Ok(start), Ok(end)) if start <= end => (start, std::cmp::min(end, len.saturating_sub(1))),
+            (Ok(start), Err(_)) if last.is_empty() => (start, len.saturating_sub(1)),
+            // The last `n` bytes
+            (Err(_), Ok(n)) if first.is_empty() && n > 0 => (len.saturating_sub(n), len.saturating_sub(1)),
+            _ => return ByteRange::Whole,
+        };
+        if start >= len {
+            return ByteRange::Unsatisfiable;
+        }
+        ByteRange::Part(start, end)
+    }
+}
+
+// A file sent with its validators, or a part of it if a range is requested
+#[derive(Debug)]
+struct FileResponse {
+    file: File,
+    path: PathBuf,
+    len: u64,
+    etag: String,
+    last_modified: String,
+    not_modified: bool,
+    range: ByteRange,
+}
+
+impl FileResponse {
+    fn open(path: &PathBuf, conditions: &RequestConditions) -> io::Result<FileResponse> {
+        let file = File::open(path)?;
+        let metadata = file.metadata()?;
+        let modified: DateTime<Utc> = metadata.modified()?.into();
+        let etag = format!("\"{:x}-{:x}-{:x}\"", metadata.ino(), metadata.len(), modified.timestamp());
+        let last_modified = modified.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+        let not_modified = conditions.not_modified(&etag, Some(&modified));
+        let range = conditions.byte_range(metadata.len(), &etag, &last_modified);
+
+        Ok(FileResponse {
+            file,
+            path: path.clone(),
+            len: metadata.len(),
+            etag,
+            last_modified,
+            not_modified,
+            range,
+        })
+    }
+}
+
+impl<'r> Responder<'r> for FileResponse {
+    fn respond_to(self, _: &Request) -> rocket::response::Result<'r> {
+        let mut response = Response::build();
+        response
+            .raw_header("Accept-Ranges", "bytes")
+            .raw_header("ETag", self.etag)
+            .raw_header("Last-Modified", self.last_modified);
+        if let Some(content_type) = self.path.extension().and_then(|ext| ext.to_str()).and_then(ContentType::from_extension) {
+            response.header(content_type);
+        }
+
+        if self.not_modified {
+            return response.status(Status::NotModified).ok();
+        }
+        match self.range {
+            ByteRange::Whole => response.sized_body(self.file),
+            ByteRange::Part(start, end) => response
+                .status(Status::PartialContent)
+                .raw_header("Content-Range", format!("bytes {}-{}/{}", start, end, self.len))
+                .sized_body(FileRange::new(self.file, start, end - start + 1)),
+            ByteRange::Unsatisfiable => response
+                .status(Status::RangeNotSatisfiable)
+                .raw_header("Content-Range", format!("bytes */{}", self.len)),
+        };
+        response.ok()
+    }
+}
+
+// A part of a file seen as a whole file, so its length is sent as the content length
+struct FileRange {
+    file: File,
+    start: u64,
+    len: u64,
+    pos: u64,
+}
+
+impl FileRange {
+    fn new(file: File, start: u64, len: u64) -> FileRange {
+        FileRange {
+            file,
+            start,
+            len,
+            pos: 0,
+        }
+    }
+}
+
+impl Read for FileRange {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let max = std::cmp::min(buf.len() as u64, self.len - self.pos) as usize;
+        if max == 0 {
+            return Ok(0);
+        }
+        self.file.seek(SeekFrom::Start(self.start + self.pos))?;
+        let n = self.file.read(&mut buf[..max])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for FileRange {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(n) => n as i64,
+            SeekFrom::End(n) => self.len as i64 + n,
+            SeekFrom::Current(n) => self.pos as i64 + n,
+        };
+        if pos < 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid seek to a negative position"));
+        }
+        self.pos = std::cmp::min(pos as u64, self.len);
+        Ok(self.pos)
+    }
+}
+
+// A response the client may keep, answered with 304 if the copy of the client is still valid
+#[derive(Debug)]
+struct Cached<R> {
+    inner: R,
+    etag: Option<String>,
+    not_modified: bool,
+}
+
+impl<R> Cached<R> {
+    fn new(inner: R, etag: Option<String>, conditions: &RequestConditions) -> Cached<R> {
+        let not_modified = etag.as_ref().map_or(false, |etag| conditions.not_modified(etag, None));
+        Cached {
+            inner,
+            etag,
+            not_modified,
+        }
+    }
+}
+
+impl<'r, R: Responder<'r>> Responder<'r> for Cached<R> {
+    fn respond_to(self, request: &Request) -> rocket::response::Result<'r> {
+        if self.not_modified {
+            let mut response = Response::build();
+            response.status(Status::NotModified);
+            if let Some(etag) = self.etag {
+                response.raw_header("ETag", etag);
+            }
+            return response.ok();
+        }
+
+        let mut response = self.inner.respond_to(request)?;
+        if let Some(etag) = self.etag {
+            response.set_raw_header("ETag", etag);
+        }
+        Ok(response)
+    }
+}
+
+fn directory_filter(dir: String) -> String {
+    unsafe {
+        let base_dir = GLOBAL_ARGS.clone().unwrap().file_dir;
+        dir.replace(base_dir.to_str().unwrap_or(""), "")
+    }
+}
+
+// Gets a list of subfiles and directories in a directory
+fn get_directory_info_render(path: &PathBuf) -> Result<IndexRender, Box<dyn Error>> {
+    let render;
+    if path.is_dir() {
+        let mut elements: Vec<IndexElement> = vec![];
+
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            let file_path = entry.path();
+            let metadata = entry.metadata()?;
+            let file_name = file_path
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .to_string();
+
+            if file_name.get(0..1) == Some(".") {
+                continue;
+            }
+
+            let len = metadata.len();
+            let atime: DateTime<Local> = metadata.modified()?.into();
+            let atime_string = atime.format("%Y-%m-%d %T").to_string();
+
+            let class = match file_path.is_dir() {
+                true => "d".to_string(),
+                false => "f".to_string(),
+            };
+            elements.push(IndexElement::new(class, file_name, atime_string, len));
+        }
+        render = IndexRender::new(
+            true,
+            "".to_string(),
+            elements,
+            Some(directory_filter(path.to_string_lossy().to_string())),
+        );
+    } else if let Some(archive_render) = get_archive_directory_render(path)? {
+        render = archive_render;
+    } else {
+        render = IndexRender::new(false, "目录配置错误".to_string(), vec![], None);
+    }
+
+    Ok(render)
+}
+
+// The ETag of a directory listing, it changes when any entry is added, removed, resized or modified
+fn listing_etag(render: &IndexRender) -> String {
+    let mut hasher = DefaultHasher::new();
+    render.list.hash(&mut hasher);
+    render.write.hash(&mut hasher);
+    format!("\"{:x}\"", hasher.finish())
+}
+
+// Gets the content of a file 
+// If the file has been truncated or replaced since `inode` was read, reads it again from the start.
+fn get_detail_render(
+    path: &PathBuf,
+    start_seek: u64,
+    inode: Option<u64>,
+    drain: bool,
+) -> Result<DetailRender, Box<dyn Error>> {
+    if is_compressed(path) {
+        return get_compressed_detail_render(path, start_seek);
+    }
+
+    let mut file = File::open(path)?;
+    let metadata = file.metadata()?;
+    let file_len = metadata.len();
+    let max_file_len = 512000;         // the max returned size, default is 512kb.
+    let mut contents = String::new();
+    let mut seek = file_len;
+    let mut window_start = 0;
+    let encoding = detect_encoding(path);
+    let mut start_seek = start_seek;
+    let replaced = inode.map_or(false, |inode| inode != metadata.ino());
+    let rotated = replaced || start_seek > file_len;
+
+    if rotated {
+        if let (true, Some(inode)) = (replaced && drain, inode) {
+            // Reads the rest of the rotated-away file first
+            if let Some(mut old_file) = find_rotated_file(path, inode) {
+                let old_len = old_file.metadata()?.len();
+                if old_len > start_seek {
+                    let (c, s, end) = read_window(&mut old_file, start_seek, max_file_len, encoding)?;
+                    if end < old_len {
+                        // More of the old file is left, the client asks again with its inode
+                        let mut render = DetailRender::new(c, directory_filter(path.to_string_lossy().to_string()), end);
+                        render.set_inode(inode);
+                        render.set_start_seek(s);
+                        render.set_encoding(encoding);
+                        render.set_eof(false);
+                        return Ok(render);
+                    }
+                    contents = c;
+                }
+            }
+        }
+        start_seek = 0;
+    }
+    
+    if start_seek > 0 || rotated {
+        // Reads the next window only, the client asks again for the rest
+        // After a rotation or truncation the new file is read from its beginning, not from its last window
+        let (c, s, end) = read_window(&mut file, start_seek, max_file_len, encoding)?;
+        contents.push_str(&c);
+        window_start = s;
+        seek = end;
+    } else if file_len > max_file_len {
+        let (c, s) = attemp_to_read_file(&mut file, file_len - max_file_len, encoding)?;
+        contents.push_str(&c);
+        window_start = s;
+    } else {
+        let mut buff = vec![];
+        file.read_to_end(&mut buff)?;
+        contents.push_str(&decode_content(&buff, encoding));
+    }
+
+    let mut render = DetailRender::new(contents, directory_filter(path.to_string_lossy().to_string()), seek);
+    render.set_inode(metadata.ino());
+    render.set_rotated(rotated);
+    render.set_start_seek(window_start);
+    render.set_encoding(encoding);
+    render.set_eof(seek >= file_len);
+    Ok(render)
+}
+
+// Gets the content of a file from `offset`, to open the file at a position other than its end.
+fn get_window_render(path: &PathBuf, offset: u64) -> Result<DetailRender, Box<dyn Error>> {
+    if is_compressed(path) {
+        return get_compressed_detail_render(path, offset);
+    }
+
+    let max_file_len = 512000;         // the max returned size, default is 512kb.
+    let mut file = File::open(path)?;
+    let metadata = file.metadata()?;
+    let encoding = detect_encoding(path);
+    let (contents, window_start, seek) = read_window(&mut file, offset, max_file_len, encoding)?;
+
+    let mut render = DetailRender::new(contents, directory_filter(path.to_string_lossy().to_string()), seek);
+    render.set_inode(metadata.ino());
+    render.set_start_seek(window_start);
+    render.set_encoding(encoding);
+    render.set_eof(seek >= metadata.len());
+    Ok(render)
+}
+
+// Finds the first line at or after `time`, by binary searching the timestamps of the lines sampled in the file.
+fn find_time_offset(path: &PathBuf, time: NaiveDateTime, time_parser: &TimeParser) -> Result<u64, Box<dyn Error>> {
+    if is_compressed(path) {
+        return Err("压缩文件不支持按时间跳转")?;
+    }
+
+    let mut file = File::open(path)?;
+    let file_len = file.metadata()?.len();
+    let encoding = detect_encoding(path);
+    let (mut low, mut high) = (0, file_len);
+
+    while low + 65536 < high {
+        let mid = low + (high - low) / 2;
+        match next_timestamp(&mut file, mid, true, time_parser, encoding)? {
+            Some((line_start, t)) if t < time && line_start < high => low = line_start,
+            _ => high = mid,
+        }
+    }
+
+    // Scans the lines from `low`, which begins a line
+    let mut next = next_timestamp(&mut file, low, false, time_parser, encoding)?;
+    while let Some((line_start, t)) = next {
+        if t >= time {
+            return Ok(line_start);
+        }
+        next = next_timestamp(&mut file, line_start, true, time_parser, encoding)?;
+    }
+    Ok(file_len)
+}
+
+// Finds the first line with a timestamp from `offset`, returns where the line begins and its time.
+// With `skip_line`, the line that `offset` is in is skipped.
+fn next_timestamp(
+    file: &mut File,
+    offset: u64,
+    skip_line: bool,
+    time_parser: &TimeParser,
+    encoding: &'static Encoding,
+) -> io::Result<Option<(u64, NaiveDateTime)>> {
+    let max_scan_lines = 1000;
+    let mut line_start = offset;
+    let mut buff = vec![];
+    file.seek(SeekFrom::Start(offset))?;
+    let mut reader = BufReader::new(Read::by_ref(file));
+
+    if skip_line {
+        line_start += reader.read_until(b'\n', &mut buff)? as u64;
+    }
+    for _ in 0..max_scan_lines {
+        buff.clear();
+        let len = reader.read_until(b'\n', &mut buff)?;
+        if len == 0 {
+            break;
+        }
+        if let Some(time) = time_parser.parse(&decode_content(&buff, encoding)) {
+            return Ok(Some((line_start, time)));
+        }
+        line_start += len as u64;
+    }
+    Ok(None)
+}
+
+// Parses the time to jump to, a time without date is on the day the file was modified.
+fn parse_jump_time(value: &str, path: &PathBuf) -> Option<NaiveDateTime> {
+    let value = value.trim();
+    // In the local time of the server, which the timestamps of the lines are compared in
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Some(local_time(time));
+    }
+    for format in &["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M"] {
+        if let Ok(time) = NaiveDateTime::parse_from_str(value, format) {
+            return Some(time);
+        }
+    }
+
+    let modified: DateTime<Local> = fs::metadata(path).ok()?.modified().ok()?.into();
+    for format in &["%H:%M:%S%.f", "%H:%M"] {
+        if let Ok(time) = NaiveTime::parse_from_str(value, format) {
+            return Some(modified.naive_local().date().and_time(time));
+        }
+    }
+    None
+}
+
+// Gets the block of a file that ends at `seek`, the block begins at a line boundary.
+fn get_previous_render(path: &PathBuf, seek: u64) -> Result<DetailRender, Box<dyn Error>> {
+    let max_file_len = 512000;         // the max returned size, default is 512kb.
+    let mut buff = vec![];
+    let encoding = detect_encoding(path);
+    let end_seek = match is_compressed(path) {
+        true => seek,
+        false => std::cmp::min(seek, fs::metadata(path)?.len()),
+    };
+    let read_start_seek = end_seek.saturating_sub(max_file_len);
+
+    if is_compressed(path) {
+        let mut reader = open_reader(path)?;
+        io::copy(&mut (&mut reader).take(read_start_seek), &mut io::sink())?;
+        reader.take(end_seek - read_start_seek).read_to_end(&mut buff)?;
+    } else {
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(read_start_seek))?;
+        file.take(end_seek - read_start_seek).read_to_end(&mut buff)?;
+    }
+    let end_seek = read_start_seek + buff.len() as u64;
+
+    // Drops the first incomplete line, a single line longer than the block is cut at a character boundary
+    let start = match read_start_seek {
+        0 => 0,
+        _ => match line_ends(&buff, read_start_seek, encoding).next() {
+            Some(end) if end < buff.len() => end,
+            _ => char_boundary(&buff, read_start_seek, encoding),
+        },
+    };
+    let contents = decode_content(&buff[start..], encoding);
+
+    let mut render = DetailRender::new(contents, directory_filter(path.to_string_lossy().to_string()), end_seek);
+    render.set_compressed(is_compressed(path));
+    render.set_start_seek(read_start_seek + start as u64);
+    render.set_encoding(encoding);
+    Ok(render)
+}
+
+// Gets the content of a compressed file, the seek counts decompressed bytes.
+fn get_compressed_detail_render(path: &PathBuf, start_seek: u64) -> Result<DetailRender, Box<dyn Error>> {
+    let (sample, reader) = open_sampled(path)?;
+    read_compressed_detail_render(path, &sample, reader, start_seek)
+}
+
+// The same with the file opened, `sample` is its first bytes
+fn read_compressed_detail_render(
+    path: &PathBuf,
+    sample: &[u8],
+    mut reader: Box<dyn Read>,
+    start_seek: u64,
+) -> Result<DetailRender, Box<dyn Error>> {
+    let max_file_len = 512000;         // the max returned size, default is 512kb.
+    let encoding = detect_encoding_with(path, sample);
+    let mut seek = io::copy(&mut (&mut reader).take(start_seek), &mut io::sink())?;
+    if start_seek > 0 {
+        // Reads the next window only, the client asks again for the rest
+        let (contents, window_start, seek, ended) = read_window_from(&mut reader, seek, max_file_len, encoding)?;
+        let mut render = DetailRender::new(contents, directory_filter(path.to_string_lossy().to_string()), seek);
+        render.set_compressed(true);
+        render.set_start_seek(window_start);
+        render.set_encoding(encoding);
+        render.set_eof(ended);
+        return Ok(render);
+    }
+
+    let mut read_start_seek = seek;
+    let mut contents = vec![];
+    let mut buff = vec![0; 65536];
+
+    loop {
+        let len = reader.read(&mut buff)?;
+        if len == 0 {
+            break;
+        }
+        contents.extend_from_slice(&buff[..len]);
+        seek += len as u64;
+
+        // Only keeps the last part of the file, the archive isn't loaded into memory at once
+        if contents.len() as u64 > max_file_len * 2 {
+            let cut = contents.len() - max_file_len as usize;
+            contents.drain(..cut);
+            read_start_seek += cut as u64;
+        }
+    }
+    if contents.len() as u64 > max_file_len {
+        let cut = contents.len() - max_file_len as usize;
+        contents.drain(..cut);
+        read_start_seek += cut as u64;
+    }
+
+    // The content cut off may begin in the middle of a multibyte character
+    let start = match read_start_seek {
+        0 => 0,
+        _ => char_boundary(&contents, read_start_seek, encoding),
+    };
+    let contents = decode_content(&contents[start..], encoding);
+
+    let mut render = DetailRender::new(contents, directory_filter(path.to_string_lossy().to_string()), seek);
+    render.set_compressed(true);
+    render.set_start_seek(read_start_seek + start as u64);
+    render.set_encoding(encoding);
+    Ok(render)
+}
+
+fn is_compressed(path: &PathBuf) -> bool {
+    matches!(path.extension().and_then(|ext| ext.to_str()), Some("gz") | Some("zst") | Some("bz2") | Some("xz"))
+        || split_archive_path(path).is_some()
+}
+
+// Opens a file for reading, compressed files are decompressed on the fly.
+fn open_reader(path: &PathBuf) -> io::Result<Box<dyn Read>> {
+    match split_archive_path(path) {
+        Some((archive, member)) => decompress(path, open_archive_member(&archive, &member)?),
+        None => decompress(path, File::open(path)?),
+    }
+}
+
+// Decompresses the content of a file by its extension
+fn decompress<'a, R: Read + 'a>(path: &Path, file: R) -> io::Result<Box<dyn Read + 'a>> {
+    let reader: Box<dyn Read + 'a> = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("gz") => Box::new(MultiGzDecoder::new(file)),
+        Some("zst") => Box::new(zstd::stream::read::Decoder::new(file)?),
+        Some("bz2") => Box::new(MultiBzDecoder::new(file)),
+        Some("xz") => Box::new(XzDecoder::new_multi_decoder(file)),
+        _ => Box::new(file),
+    };
+    Ok(reader)
+}
+
+// An archive stands for a directory of its members
+fn is_archive(path: &Path) -> bool {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("").to_lowercase();
+    [".tar", ".tar.gz", ".tgz", ".tar.zst", ".tar.bz2", ".tar.xz", ".zip"]
+        .iter()
+        .any(|ext| name.ends_with(ext))
+        && path.is_file()
+}
+
+// Splits a path in an archive into the archive and the member, such as `bundle.tar.gz` and `var/log/app.log`
+fn split_archive_path(path: &Path) -> Option<(PathBuf, String)> {
+    let archive = path.ancestors().skip(1).find(|ancestor| is_archive(ancestor))?;
+    let member = path.strip_prefix(archive).ok()?.to_string_lossy().to_string();
+    Some((archive.to_path_buf(), member))
+}
+
+// The archive of an archive or a path in it, and the path in the archive
+fn archive_location(path: &PathBuf) -> Option<(PathBuf, String)> {
+    match is_archive(path) {
+        true => Some((path.clone(), String::new())),
+        false => split_archive_path(path),
+    }
+}
+
+#[derive(Debug)]
+struct ArchiveEntry {
+    name: String,
+    size: u64,
+    date: String,
+    modified: Option<SystemTime>,
+    dir: bool,
+}
+
+// The member names are relative, without the leading `./` or trailing `/`
+fn normalize_member(name: &str) -> String {
+    name.trim_start_matches("./").trim_matches('/').to_owned()
+}
+
+fn open_tar(archive: &Path) -> io::Result<Box<dyn Read>> {
+    let name = archive.file_name().and_then(|n| n.to_str()).unwrap_or("").to_lowercase();
+    match name.ends_with(".tgz") {
+        true => Ok(Box::new(MultiGzDecoder::new(File::open(archive)?))),
+        false => open_reader(&archive.to_path_buf()),
+    }
+}
+
+// Lists all members of an archive, a tar archive is decompressed as a stream to read its headers
+fn list_archive(archive: &Path) -> io::Result<Vec<ArchiveEntry>> {
+    let mut entries = vec![];
+    if archive.extension().and_then(|ext| ext.to_str()) == Some("zip") {
+        let mut zip = ZipArchive::new(File::open(archive)?)?;
+        for i in 0..zip.len() {
+            entries.push(zip_entry(&zip.by_index(i)?));
+        }
+    } else {
+        let mut tar = tar::Archive::new(open_tar(archive)?);
+        for entry in tar.entries()? {
+            entries.push(tar_entry(&entry?)?);
+        }
+    }
+    Ok(entries)
+}
+
+fn zip_entry(file: &zip::read::ZipFile) -> ArchiveEntry {
+    let time = file.last_modified();
+    let modified = NaiveDate::from_ymd_opt(time.year() as i32, time.month() as u32, time.day() as u32)
+        .and_then(|d| d.and_hms_opt(time.hour() as u32, time.minute() as u32, time.second() as u32));
+    ArchiveEntry {
+        name: normalize_member(file.name()),
+        size: file.size(),
+        date: modified.map(|d| d.format("%Y-%m-%d %T").to_string()).unwrap_or_default(),
+        modified: modified.and_then(|d| Local.from_local_datetime(&d).single()).map(SystemTime::from),
+        dir: file.is_dir(),
+    }
+}
+
+fn tar_entry<R: Read>(entry: &tar::Entry<R>) -> io::Result<ArchiveEntry> {
+    let header = entry.header();
+    let mtime = header.mtime()?;
+    Ok(ArchiveEntry {
+        name: normalize_member(&entry.path()?.to_string_lossy()),
+        size: header.size()?,
+        date: Local.timestamp(mtime as i64, 0).format("%Y-%m-%d %T").to_string(),
+        modified: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(mtime)),
+        dir: header.entry_type().is_dir(),
+    })
+}
+
+// Lists an archive, or a directory in it, as a virtual directory.
+// Returns nothing if the path isn't in an archive or is a file in it.
+fn get_archive_directory_render(path: &PathBuf) -> Result<Option<IndexRender>, Box<dyn Error>> {
+    let (archive, prefix) = match archive_location(path) {
+        Some(location) => location,
+        None => return Ok(None),
+    };
+    let entries = list_archive(&archive)?;
+    if entries.iter().any(|e| !e.dir && e.name == prefix) {
+        return Ok(None);
+    }
+
+    let prefix_dir = match prefix.is_empty() {
+        true => String::new(),
+        false => format!("{}/", prefix),
+    };
+    let mut elements: Vec<IndexElement> = vec![];
+    for entry in &entries {
+        let rest = match entry.name.strip_prefix(prefix_dir.as_str()) {
+            Some(rest) if !rest.is_empty() => rest,
+            _ => continue,
+        };
+        // The directories may have no entries of their own
+        let (name, dir) = match rest.find('/') {
+            Some(pos) => (&rest[..pos], true),
+            None => (rest, entry.dir),
+        };
+        if elements.iter().any(|e| e.name == name) {
+            continue;
+        }
+        let class = if dir { "d" } else { "f" };
+        let size = if dir { 0 } else { entry.size };
+        elements.push(IndexElement::new(class.to_owned(), name.to_owned(), entry.date.clone(), size));
+    }
+    if !prefix.is_empty() && elements.is_empty() {
+        return Err("压缩包中没有这个文件".into());
+    }
+
+    Ok(Some(IndexRender::new(
+        true,
+        "".to_string(),
+        elements,
+        Some(directory_filter(path.to_string_lossy().to_string())),
+    )))
+}
+
+// The paths of the files in an archive, or in a directory of it
+fn get_archive_files(path: &PathBuf) -> io::Result<Option<Vec<PathBuf>>> {
+    let (archive, prefix) = match archive_location(path) {
+        Some(location) => location,
+        None => return Ok(None),
+    };
+    let prefix_dir = match prefix.is_empty() {
+        true => String::new(),
+        false => format!("{}/", prefix),
+    };
+    let files: Vec<PathBuf> = list_archive(&archive)?
+        .into_iter()
+        .filter(|e| !e.dir)
+        .filter_map(|e| e.name.strip_prefix(prefix_dir.as_str()).map(|rest| path.join(rest)))
+        .collect();
+    match files.is_empty() && !prefix.is_empty() {
+        // A file in the archive
+        true => Ok(None),
+        false => Ok(Some(files)),
+    }
+}
+
+// A member of an archive read as a stream.
+// The member can't outlive the iteration over the archive, so it is read by a thread and sent in chunks.
+#[derive(Debug)]
+struct MemberReader {
+    chunks: Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for MemberReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos >= self.chunk.len() {
+            match self.chunks.recv() {
+                Ok(Ok(chunk)) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+                Ok(Err(e)) => return Err(e),
+                // The whole member has been sent
+                Err(_) => return Ok(0),
+            }
+        }
+        let len = std::cmp::min(buf.len(), self.chunk.len() - self.pos);
+        buf[..len].copy_from_slice(&self.chunk[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+fn open_archive_member(archive: &PathBuf, member: &str) -> io::Result<MemberReader> {
+    let (sender, chunks) = sync_channel(16);
+    let archive = archive.clone();
+    let member = normalize_member(member);
+    thread::spawn(move || {
+        let error = match send_archive_member(&archive, &member, &sender) {
+            Ok(true) => return,
+            Ok(false) => io::Error::new(io::ErrorKind::NotFound, "压缩包中没有这个文件"),
+            Err(e) => e,
+        };
+        let _ = sender.send(Err(error));
+    });
+
+    // An empty chunk is sent first once the member is found
+    match chunks.recv() {
+        Ok(Ok(_)) => Ok(MemberReader {
+            chunks,
+            chunk: vec![],
+            pos: 0,
+        }),
+        Ok(Err(e)) => Err(e),
+        Err(_) => Err(io::Error::new(io::ErrorKind::NotFound, "压缩包中没有这个文件")),
+    }
+}
+
+// Finds the member and sends it until the reader is dropped, returns whether the member has been found
+fn send_archive_member(archive: &Path, member: &str, sender: &SyncSender<io::Result<Vec<u8>>>) -> io::Result<bool> {
+    let mut found = false;
+    for_each_archive_member(archive, |entry, reader| {
+        if entry.name != member {
+            return true;
+        }
+        send_chunks(reader, sender);
+        found = true;
+        false
+    })?;
+    Ok(found)
+}
+
+// Reads the files in an archive in one pass, `on_member` is given the entry and the content of each,
+// the reading stops when it returns false
+fn for_each_archive_member<F>(archive: &Path, mut on_member: F) -> io::Result<()>
+where
+    F: FnMut(&ArchiveEntry, &mut dyn Read) -> bool,
+{
+    if archive.extension().and_then(|ext| ext.to_str()) == Some("zip") {
+        let mut zip = ZipArchive::new(File::open(archive)?)?;
+        for i in 0..zip.len() {
+            let mut file = zip.by_index(i)?;
+            let entry = zip_entry(&file);
+            if !entry.dir && !on_member(&entry, &mut file) {
+                break;
+            }
+        }
+    } else {
+        let mut tar = tar::Archive::new(open_tar(archive)?);
+        for file in tar.entries()? {
+            let mut file = file?;
+            let entry = tar_entry(&file)?;
+            if !entry.dir && !on_member(&entry, &mut file) {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn send_chunks<R: Read>(mut reader: R, sender: &SyncSender<io::Result<Vec<u8>>>) {
+    if sender.send(Ok(vec![])).is_err() {
+        return;
+    }
+    loop {
+        let mut buff = vec![0; 65536];
+        match reader.read(&mut buff) {
+            Ok(0) => return,
+            Ok(len) => {
+                buff.truncate(len);
+                // The reader has been dropped
+                if sender.send(Ok(buff)).is_err() {
+                    return;
+                }
+            }
+            Err(e) => {
+                let _ = sender.send(Err(e));
+                return;
+            }
+        }
+    }
+}
+
+// Gets a page of a file as hex dump, 16 bytes per row.
+fn get_hex_render(path: &PathBuf, seek: u64) -> Result<HexRender, Box<dyn Error>> {
+    let page_size = 4096;           // the bytes of a page, 256 rows
+    let row_size = 16;
+    let seek = seek - seek % row_size;
+    let mut buff = vec![];
+    let file_len;
+
+    // Reads one more byte to know whether there is a next page
+    if is_compressed(path) {
+        let mut reader = open_reader(path)?;
+        io::copy(&mut (&mut reader).take(seek), &mut io::sink())?;
+        reader.take(page_size + 1).read_to_end(&mut buff)?;
+        // The decompressed size is unknown without reading to the end
+        file_len = None;
+    } else {
+        let mut file = File::open(path)?;
+        file_len = Some(file.metadata()?.len());
+        file.seek(SeekFrom::Start(seek))?;
+        file.take(page_size + 1).read_to_end(&mut buff)?;
+    }
+    let has_next = buff.len() as u64 > page_size;
+    buff.truncate(page_size as usize);
+
+    let rows = buff
+        .chunks(row_size as usize)
+        .enumerate()
+        .map(|(i, bytes)| HexRow::new(seek + (i as u64) * row_size, bytes))
+        .collect();
+
+    Ok(HexRender::new(
+        directory_filter(path.to_string_lossy().to_string()),
+        seek,
+        file_len,
+        rows,
+        page_size,
+        has_next,
+    ))
+}
+
+// Gets the files of a timeline, listed in `files` or matched by `glob` under the directory.
+fn get_timeline_files(
+    file_dir: &PathBuf,
+    dir: &PathBuf,
+    files: &[String],
+    glob: &Option<String>,
+) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut paths = vec![];
+
+    for file in files.iter().filter(|f| !f.is_empty()) {
+        paths.push(file_dir.join(path_to_relative(&PathBuf::from(file))));
+    }
+    if let Some(glob) = glob {
+        let matcher = Glob::new(glob)?.compile_matcher();
+        let mut dir_files = vec![];
+        walk_files(dir, &mut dir_files)?;
+        for path in dir_files {
+            if matcher.is_match(path.strip_prefix(dir).unwrap_or(path.as_path())) {
+                paths.push(path);
+            }
+        }
+    }
+    paths.sort();
+    paths.dedup();
+
+    if paths.is_empty() {
+        return Err("没有找到文件")?;
+    }
+    if paths.len() > MAX_TIMELINE_FILES {
+        return Err(format!("文件太多，最多合并{}个文件", MAX_TIMELINE_FILES))?;
+    }
+    Ok(paths)
+}
+
+// Gets all files under a directory, except the hidden ones.
+fn walk_files(dir: &PathBuf, paths: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.file_name().map_or(true, |name| name.to_string_lossy().starts_with(".")) {
+            continue;
+        }
+        if path.is_dir() {
+            walk_files(&path, paths)?;
+        } else {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+// Gets the last part of several files merged by the timestamps of their lines.
+fn get_timeline_render(paths: &[PathBuf], time_parser: &TimeParser) -> Result<TimelineRender, Box<dyn Error>> {
+    let mut sources = vec![];
+    let mut entries = vec![];
+
+    for (i, path) in paths.iter().enumerate() {
+        let render = get_detail_render(path, 0, None, false)?;
+        entries.extend(time_parser.parse_entries(&render.content, i));
+        let mut source = TimelineSource::new(render.file_path, render.seek, render.inode);
+        source.set_compressed(render.compressed);
+        sources.push(source);
+    }
+    entries.sort_by_key(|e| e.timestamp);
+
+    Ok(TimelineRender::new(sources, entries))
+}
+
+// Gets the JSON records of a file after `seek`
+// With a filter and no `seek`, the whole file is scanned for the last matched records.
+fn get_jsonl_render(
+    path: &PathBuf,
+    seek: u64,
+    inode: Option<u64>,
+    filter: &JsonFilter,
+) -> Result<JsonlRender, Box<dyn Error>> {
+    let max_records = 1000;        // the max records returned by the first page
+    let file_path = directory_filter(path.to_string_lossy().to_string());
+
+    if seek == 0 && !filter.is_empty() {
+        let encoding = detect_encoding(path);
+        let mut reader = BufReader::new(open_reader(path)?);
+        let mut records = VecDeque::new();
+        let mut line = vec![];
+        let mut seek = 0;
+        loop {
+            line.clear();
+            let len = read_line(&mut reader, encoding, &mut line)?;
+            // The last line is left to the tail until it is complete
+            if len == 0 || !line.ends_with(b"\n") && !line.ends_with(b"\n\0") {
+                break;
+            }
+            seek += len as u64;
+            for record in parse_records(&decode_content(&line, encoding), filter) {
+                if records.len() == max_records {
+                    records.pop_front();
+                }
+                records.push_back(record);
+            }
+        }
+        let inode = match is_compressed(path) {
+            true => 0,
+            false => fs::metadata(path)?.ino(),
+        };
+        return Ok(JsonlRender::new(file_path, records.into_iter().collect(), seek, inode));
+    }
+
+    let render = get_detail_render(path, seek, inode, true)?;
+    let mut content = render.content.as_str();
+    let mut end_seek = render.seek;
+    if seek == 0 && render.start_seek > 0 {
+        // The window starts in the middle of a line
+        content = content.splitn(2, '\n').nth(1).unwrap_or("");
+    }
+    if render.encoding == UTF_8.name() && !render.compressed {
+        // The last line is left to the tail until it is complete
+        // The seek is found in the raw bytes, the invalid ones are decoded to longer replacement characters
+        let end = content.rfind('\n').map_or(0, |i| i + 1);
+        content = &content[..end];
+        let file = match File::open(path) {
+            Ok(file) if file.metadata()?.ino() == render.inode => Some(file),
+            _ => find_rotated_file(path, render.inode),
+        };
+        if let Some(mut file) = file {
+            end_seek = last_line_end(&mut file, render.start_seek, end_seek)?.unwrap_or(render.start_seek);
+        }
+    }
+
+    let mut records = parse_records(content, filter);
+    if seek == 0 && records.len() > max_records {
+        records.drain(..records.len() - max_records);
+    }
+    let mut jsonl_render = JsonlRender::new(file_path, records, end_seek, render.inode);
+    jsonl_render.set_rotated(render.rotated);
+    Ok(jsonl_render)
+}
+
+// Reads a line including its newline, the zero byte after the newline of UTF-16LE belongs to the line.
+// The reader is at the beginning of a line, in UTF-16 the 0x0A of other characters is read past.
+fn read_line<R: BufRead>(reader: &mut R, encoding: &'static Encoding, line: &mut Vec<u8>) -> io::Result<usize> {
+    let start = line.len();
+    while reader.read_until(b'\n', line)? > 0 && line.ends_with(b"\n") {
+        let unit = match newline_unit(encoding) {
+            Some(unit) => unit,
+            None => break,
+        };
+        if unit[1] == 0 && (line.len() - start) % 2 == 1 {
+            let mut zero = [0; 1];
+            if reader.read(&mut zero)? == 0 {
+                break;
+            }
+            line.push(zero[0]);
+        }
+        if (line.len() - start) % 2 == 0 && line.ends_with(&unit) {
+            break;
+        }
+    }
+    Ok(line.len() - start)
+}
+
+// The newline code unit of UTF-16, a single 0x0A byte is the newline of the other encodings.
+fn newline_unit(encoding: &'static Encoding) -> Option<[u8; 2]> {
+    if encoding == UTF_16LE {
+        Some([b'\n', 0])
+    } else if encoding == UTF_16BE {
+        Some([0, b'\n'])
+    } else {
+        None
+    }
+}
+
+// The positions right after the newlines of a buffer read from `offset`.
+// In UTF-16 a newline is a whole code unit at an even offset, 0x0A may be a byte of another character.
+fn line_ends<'a>(buff: &'a [u8], offset: u64, encoding: &'static Encoding) -> Box<dyn Iterator<Item = usize> + 'a> {
+    match newline_unit(encoding) {
+        Some(unit) => {
+            let start = std::cmp::min((offset % 2) as usize, buff.len());
+            let units = buff[start..].chunks_exact(2).enumerate();
+            Box::new(units.filter(move |(_, u)| *u == unit).map(move |(i, _)| start + i * 2 + 2))
+        }
+        None => Box::new(buff.iter().enumerate().filter(|(_, b)| **b == b'\n').map(|(i, _)| i + 1)),
+    }
+}
+
+// Finds where the last complete line between `start` and `end` of a file ends, reading backwards from `end`.
+fn last_line_end(file: &mut File, start: u64, end: u64) -> io::Result<Option<u64>> {
+    let mut buff = vec![0; 8192];
+    let mut pos = end;
+    while pos > start {
+        let len = std::cmp::min(pos - start, buff.len() as u64) as usize;
+        pos -= len as u64;
+        file.seek(SeekFrom::Start(pos))?;
+        file.read_exact(&mut buff[..len])?;
+        if let Some(i) = buff[..len].iter().rposition(|b| *b == b'\n') {
+            return Ok(Some(pos + i as u64 + 1));
+        }
+    }
+    Ok(None)
+}
+
+// Parses the lines into JSON records, the lines that aren't JSON objects always pass the filter
+fn parse_records(content: &str, filter: &JsonFilter) -> Vec<JsonRecord> {
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(JsonRecord::parse)
+        .filter(|record| filter.matches(record))
+        .collect()
+}
+
+// Whether a file is JSON lines, by the extension or the first line
+fn is_jsonl(path: &PathBuf) -> bool {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    extension == "jsonl" || extension == "ndjson" || is_jsonl_with(path, &read_sample(path))
+}
+
+fn is_jsonl_with(path: &PathBuf, sample: &[u8]) -> bool {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    if extension == "jsonl" || extension == "ndjson" {
+        return true;
+    }
+
+    match sample.iter().position(|b| *b == b'\n') {
+        Some(pos) => serde_json::from_slice::<Value>(&sample[..pos]).map_or(false, |v| v.is_object()),
+        None => false,
+    }
+}
+
+// Detects the language of a file by the configured globs, the extension, the shebang or the first bytes.
+// Files in no known language are `log`, they are tailed instead of highlighted.
+fn detect_language(path: &PathBuf, args: &Args) -> String {
+    let mut sample = vec![];
+    if let Ok(reader) = open_reader(path) {
+        let _ = reader.take(256).read_to_end(&mut sample);
+    }
+    detect_language_with(path, args, &sample)
+}
+
+fn detect_language_with(path: &PathBuf, args: &Args, sample: &[u8]) -> String {
+    let relative_path = directory_filter(path.to_string_lossy().to_string());
+    let relative_path = relative_path.trim_start_matches('/');
+    if let Some((_, language)) = args.file_types.iter().find(|(matcher, _)| matcher.is_match(relative_path)) {
+        return language.clone();
+    }
+
+    // The extension under the compression one, such as `app.js.gz`
+    let mut name = path.file_name().and_then(|n| n.to_str()).unwrap_or("").to_lowercase();
+    if is_compressed(path) {
+        if let Some(pos) = name.rfind('.') {
+            name.truncate(pos);
+        }
+    }
+    let extension = match name.rfind('.') {
+        Some(pos) => &name[pos + 1..],
+        None => "",
+    };
+    let language = match extension {
+        "rs" => "rust",
+        "js" | "mjs" => "javascript",
+        "ts" => "typescript",
+        "php" => "php",
+        "html" | "htm" => "html",
+        "xml" => "xml",
+        "py" => "python",
+        "sh" | "bash" => "bash",
+        "json" => "json",
+        "yml" | "yaml" => "yaml",
+        "toml" | "ini" => "ini",
+        "css" => "css",
+        "c" | "h" => "c",
+        "cpp" | "cc" | "hpp" => "cpp",
+        "java" => "java",
+        "go" => "go",
+        "sql" => "sql",
+        "md" | "markdown" => "markdown",
+        "csv" => "csv",
+        "tsv" => "tsv",
+        "rb" => "ruby",
+        "pl" => "perl",
+        _ => "",
+    };
+    if !language.is_empty() {
+        return language.to_owned();
+    }
+
+    let sample = String::from_utf8_lossy(&sample[..std::cmp::min(sample.len(), 256)]);
+    let first_line = sample.trim_start_matches('\u{feff}').lines().next().unwrap_or("");
+    if first_line.starts_with("#!") {
+        // Such as `#!/bin/bash` or `#!/usr/bin/env python3`
+        let mut words = first_line[2..].split_whitespace().map(|w| w.rsplit('/').next().unwrap_or(w));
+        let interpreter = match words.next() {
+            Some("env") => words.next().unwrap_or(""),
+            Some(interpreter) => interpreter,
+            None => "",
+        };
+        let language = match interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.') {
+            "python" => "python",
+            "sh" | "bash" | "zsh" | "dash" => "bash",
+            "node" => "javascript",
+            "perl" => "perl",
+            "ruby" => "ruby",
+            "php" => "php",
+            _ => "bash",
+        };
+        return language.to_owned();
+    }
+
+    let head = first_line.to_lowercase();
+    if head.starts_with("<?php") {
+        "php".to_owned()
+    } else if head.starts_with("<?xml") {
+        "xml".to_owned()
+    } else if head.starts_with("<!doctype html") || head.starts_with("<html") {
+        "html".to_owned()
+    } else {
+        "log".to_owned()
+    }
+}
+
+// Whether the files in a language are previewed rendered rather than shown as raw text
+fn is_previewable(language: &str) -> bool {
+    matches!(language, "markdown" | "csv" | "tsv" | "json" | "yaml")
+}
+
+// Whether a language is source code, data and text files such as JSON, YAML, XML, INI, SQL or CSV are tailed like logs
+fn is_source_code(language: &str) -> bool {
+    matches!(
+        language,
+        "rust" | "javascript" | "typescript" | "php" | "html" | "python" | "bash" | "css" | "c" | "cpp" | "java" | "go" | "ruby" | "perl"
+    )
+}
+
+// Renders a Markdown, CSV/TSV, JSON or YAML file
+// Tables are sorted by the column `sort` if there is one, then cut into pages.
+fn get_preview_render(
+    path: &PathBuf,
+    language: &str,
+    page: usize,
+    sort: Option<usize>,
+    desc: bool,
+) -> Result<PreviewRender, Box<dyn Error>> {
+    let max_preview_len = 10485760;    // the max size of a previewed file, default is 10mb.
+    let page_size = 200;
+    let mut buff = vec![];
+    open_reader(path)?.take(max_preview_len + 1).read_to_end(&mut buff)?;
+    if buff.len() as u64 > max_preview_len {
+        return Err("文件太大，无法预览".into());
+    }
+    let text = decode_content(&buff, detect_encoding(path));
+    let mut render = PreviewRender::new(directory_filter(path.to_string_lossy().to_string()));
+
+    match language {
+        "markdown" => {
+            let mut options = MarkdownOptions::empty();
+            options.insert(MarkdownOptions::ENABLE_TABLES);
+            options.insert(MarkdownOptions::ENABLE_STRIKETHROUGH);
+            options.insert(MarkdownOptions::ENABLE_TASKLISTS);
+            let mut html = String::new();
+            markdown_html::push_html(&mut html, MarkdownParser::new_ext(&text, options));
+            // Runbooks may be written by anyone, the scripts and handlers are removed
+            render.set_markdown(ammonia::clean(&html));
+        }
+        "json" | "yaml" => {
+            let value: Value = match language {
+                "json" => serde_json::from_str(&text)?,
+                _ => serde_yaml::from_str(&text)?,
+            };
+            render.set_tree(serde_json::to_string(&value)?.replace("</", "<\\/"));
+        }
+        _ => {
+            let delimiter = match language {
+                "tsv" => b'\t',
+                _ => sniff_delimiter(text.lines().next().unwrap_or("")),
+            };
+            let mut reader = csv::ReaderBuilder::new()
+                .delimiter(delimiter)
+                .has_headers(false)
+                .flexible(true)
+                .from_reader(text.as_bytes());
+            let mut rows = vec![];
+            for record in reader.records() {
+                rows.push(record?.iter().map(|cell| cell.to_owned()).collect::<Vec<String>>());
+            }
+
+            let header = match rows.first() {
+                Some(row) if is_table_header(row) => rows.remove(0),
+                Some(row) => (1..=row.len()).map(|i| format!("第{}列", i)).collect(),
+                None => vec![],
+            };
+            if let Some(column) = sort {
+                rows.sort_by(|a, b| {
+                    let (a, b) = (a.get(column).map_or("", |c| c.as_str()), b.get(column).map_or("", |c| c.as_str()));
+                    let ordering = match (a.trim().parse::<f64>(), b.trim().parse::<f64>()) {
+                        (Ok(a), Ok(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+                        _ => a.cmp(b),
+                    };
+                    if desc { ordering.reverse() } else { ordering }
+                });
+            }
+
+            let total_rows = rows.len();
+            let pages = std::cmp::max((total_rows + page_size - 1) / page_size, 1);
+            let page = std::cmp::min(std::cmp::max(page, 1), pages);
+            let rows = rows.into_iter().skip((page - 1) * page_size).take(page_size).collect();
+            render.set_table(header, rows, total_rows, page, pages);
+            render.set_sort(sort, desc);
+        }
+    }
+    Ok(render)
+}
+
+// Guesses the delimiter of a CSV file by the first line
+fn sniff_delimiter(line: &str) -> u8 {
+    [b',', b';', b'\t', b'|']
+        .iter()
+        .cloned()
+        .max_by_key(|d| line.bytes().filter(|b| b == d).count())
+        .unwrap_or(b',')
+}
+
+// A header row has names only, no empty, numeric or repeated cells
+fn is_table_header(row: &[String]) -> bool {
+    let mut names: Vec<&str> = row.iter().map(|cell| cell.trim()).collect();
+    if names.iter().any(|name| name.is_empty() || name.parse::<f64>().is_ok()) {
+        return false;
+    }
+    names.sort_unstable();
+    names.dedup();
+    names.len() == row.len()
+}
+
+// Reads the lines of a part of a file to compare, with the number of its first line
+fn read_diff_side(path: &PathBuf, range: &DiffRange, line_indexes: &LineIndexes) -> Result<(Vec<String>, usize), Box<dyn Error>> {
+    let max_diff_len = 5242880;        // the max size of a compared part, default is 5mb.
+    let encoding = detect_encoding(path);
+    let mut reader = open_reader(path)?;
+    let mut buff = vec![];
+    let mut start_line = 1;
+
+    match *range {
+        DiffRange::Whole => {
+            reader.take(max_diff_len + 1).read_to_end(&mut buff)?;
+        }
+        DiffRange::Lines(first, last) => {
+            let mut reader = BufReader::new(reader);
+            let mut line = vec![];
+            for number in 1..=last {
+                line.clear();
+                if read_line(&mut reader, encoding, &mut line)? == 0 {
+                    break;
+                }
+                if number >= first {
+                    buff.extend_from_slice(&line);
+                    if buff.len() as u64 > max_diff_len {
+                        break;
+                    }
+                }
+            }
+            start_line = first;
+        }
+        DiffRange::Bytes(first, last) => {
+            io::copy(&mut (&mut reader).take(first), &mut io::sink())?;
+            reader.take(std::cmp::min(last.saturating_sub(first) + 1, max_diff_len + 1)).read_to_end(&mut buff)?;
+            let start = char_boundary(&buff, first, encoding);
+            buff.drain(..start);
+            if !is_compressed(path) {
+                if let Ok(line) = line_indexes.line_of_offset(path, first + start as u64) {
+                    start_line = line as usize + 1;
+                }
+            }
+        }
+    }
+    if buff.len() as u64 > max_diff_len {
+        return Err("要对比的内容太大，请指定范围".into());
+    }
+
+    let lines = decode_content(&buff, encoding).lines().map(|line| line.to_owned()).collect();
+    Ok((lines, start_line))
+}
+
+// Compares two files or two parts of files line by line, the unchanged lines far from the changes are skipped.
+// With a time parser, the leading timestamps are ignored so that otherwise identical lines line up.
+fn get_diff_rows(
+    left: (&PathBuf, &DiffRange),
+    right: (&PathBuf, &DiffRange),
+    side_by_side: bool,
+    time_parser: Option<&TimeParser>,
+    line_indexes: &LineIndexes,
+) -> Result<Vec<DiffRow>, Box<dyn Error>> {
+    let context_lines = 3;
+    let (old, old_start) = read_diff_side(left.0, left.1, line_indexes)?;
+    let (new, new_start) = read_diff_side(right.0, right.1, line_indexes)?;
+    let normalize = |lines: &[String]| -> Vec<String> {
+        lines
+            .iter()
+            .map(|line| match time_parser {
+                Some(parser) => parser.strip(line).trim_start().to_owned(),
+                None => line.clone(),
+            })
+            .collect()
+    };
+    let (old_keys, new_keys) = (normalize(&old), normalize(&new));
+    // Gives up on the minimal diff for files too different, a correct one is returned anyway
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let ops = capture_diff_slices_deadline(Algorithm::Myers, &old_keys, &new_keys, Some(deadline));
+
+    let mut rows = vec![];
+    for group in group_diff_ops(ops, context_lines) {
+        let (first, last) = match (group.first(), group.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => continue,
+        };
+        let (old_range, new_range) = (first.old_range().start..last.old_range().end, first.new_range().start..last.new_range().end);
+        rows.push(DiffRow::hunk(format!(
+            "@@ -{},{} +{},{} @@",
+            old_range.start + old_start,
+            old_range.len(),
+            new_range.start + new_start,
+            new_range.len()
+        )));
+
+        let left_line = |i: usize| Some((i + old_start, old[i].as_str()));
+        let right_line = |i: usize| Some((i + new_start, new[i].as_str()));
+        for op in group {
+            match op {
+                DiffOp::Equal { old_index, new_index, len } => {
+                    for i in 0..len {
+                        rows.push(DiffRow::new("equal", " ", left_line(old_index + i), right_line(new_index + i)));
+                    }
+                }
+                DiffOp::Delete { old_index, old_len, .. } => {
+                    for i in old_index..old_index + old_len {
+                        rows.push(DiffRow::new("delete", "-", left_line(i), None));
+                    }
+                }
+                DiffOp::Insert { new_index, new_len, .. } => {
+                    for i in new_index..new_index + new_len {
+                        rows.push(DiffRow::new("insert", "+", None, right_line(i)));
+                    }
+                }
+                DiffOp::Replace { old_index, old_len, new_index, new_len } if side_by_side => {
+                    // The changed lines are put side by side
+                    for i in 0..std::cmp::max(old_len, new_len) {
+                        let left = if i < old_len { left_line(old_index + i) } else { None };
+                        let right = if i < new_len { right_line(new_index + i) } else { None };
+                        rows.push(DiffRow::new("replace", "~", left, right));
+                    }
+                }
+                DiffOp::Replace { old_index, old_len, new_index, new_len } => {
+                    for i in old_index..old_index + old_len {
+                        rows.push(DiffRow::new("delete", "-", left_line(i), None));
+                    }
+                    for i in new_index..new_index + new_len {
+                        rows.push(DiffRow::new("insert", "+", None, right_line(i)));
+                    }
+                }
+            }
+        }
+    }
+    Ok(rows)
+}
+
+// Gets the encoding configured for a path by `--encoding`.
+fn configured_encoding(path: &PathBuf) -> Option<&'static Encoding> {
+    let relative_path = directory_filter(path.to_string_lossy().to_string());
+    let relative_path = relative_path.trim_start_matches('/');
+    unsafe {
+        GLOBAL_ARGS
+            .as_ref()?
+            .encodings
+            .iter()
+            .find(|(matcher, _)| matcher.is_match(relative_path))
+            .map(|(_, encoding)| *encoding)
+    }
+}
+
+// Detects the encoding of a file, by the configuration or the content at the beginning of the file.
+fn detect_encoding(path: &PathBuf) -> &'static Encoding {
+    match configured_encoding(path) {
+        Some(encoding) => encoding,
+        None => sniff_encoding(&read_sample(path)),
+    }
+}
+
+// The same with the first bytes of the file already read
+fn detect_encoding_with(path: &PathBuf, sample: &[u8]) -> &'static Encoding {
+    configured_encoding(path).unwrap_or_else(|| sniff_encoding(sample))
+}
+
+// The first bytes of a file, the kind and the encoding of the file are detected with them
+fn read_sample(path: &PathBuf) -> Vec<u8> {
+    let mut sample = vec![];
+    if let Ok(reader) = open_reader(path) {
+        let _ = reader.take(8192).read_to_end(&mut sample);
+    }
+    sample
+}
+
+// Opens a file with its first bytes read, so a compressed file is decompressed once for the detections and the content.
+// The reader gives the whole content, the first bytes included.
+fn open_sampled(path: &PathBuf) -> io::Result<(Vec<u8>, Box<dyn Read>)> {
+    sample_reader(open_reader(path)?)
+}
+
+fn sample_reader<'a, R: Read + 'a>(mut reader: R) -> io::Result<(Vec<u8>, Box<dyn Read + 'a>)> {
+    let mut sample = vec![];
+    (&mut reader).take(8192).read_to_end(&mut sample)?;
+    Ok((sample.clone(), Box::new(io::Cursor::new(sample).chain(reader))))
+}
+
+fn sniff_encoding(sample: &[u8]) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(sample) {
+        return encoding;
+    }
+
+    // The sample may end in the middle of a character
+    match str::from_utf8(sample) {
+        Ok(_) => return UTF_8,
+        Err(e) if e.error_len().is_none() => return UTF_8,
+        Err(_) => {}
+    }
+
+    // ASCII characters in UTF-16 have a zero byte at one side
+    // Some CJK characters such as 一 (U+4E00) have one at the other side, so the sides are compared by ratio
+    let even_zeros = sample.iter().step_by(2).filter(|b| **b == 0).count();
+    let odd_zeros = sample.iter().skip(1).step_by(2).filter(|b| **b == 0).count();
+    if odd_zeros > sample.len() / 32 && odd_zeros > even_zeros * 4 {
+        return UTF_16LE;
+    }
+    if even_zeros > sample.len() / 32 && even_zeros > odd_zeros * 4 {
+        return UTF_16BE;
+    }
+
+    // GB18030 is compatible with GBK and GB2312
+    // The sample is decoded up to the last byte that no character contains, it may end in the middle of one
+    let end = sample.iter().rposition(|b| *b < 0x30).map_or(sample.len().saturating_sub(4), |i| i + 1);
+    let (_, had_errors) = GB18030.decode_without_bom_handling(&sample[..end]);
+    if !had_errors {
+        return GB18030;
+    }
+
+    WINDOWS_1252
+}
+
+// A file is binary if there are zero bytes at the beginning, which isn't text in UTF-16.
+fn is_binary(path: &PathBuf) -> bool {
+    configured_encoding(path).is_none() && is_binary_with(path, &read_sample(path))
+}
+
+fn is_binary_with(path: &PathBuf, sample: &[u8]) -> bool {
+    configured_encoding(path).is_none() && sample.contains(&0) && !is_utf16(sniff_encoding(sample))
+}
+
+fn is_utf16(encoding: &'static Encoding) -> bool {
+    encoding == UTF_16LE || encoding == UTF_16BE
+}
+
+// Gets where the first complete character begins in the content read from `seek`.
+fn char_boundary(buff: &[u8], seek: u64, encoding: &'static Encoding) -> usize {
+    if encoding == UTF_8 {
+        buff.iter().take(3).position(|b| b & 0xC0 != 0x80).unwrap_or(0)
+    } else if is_utf16(encoding) {
+        (seek % 2) as usize
+    } else if encoding == GB18030 {
+        gb18030_boundary(buff)
+    } else {
+        0
+    }
+}
+
+// GB18030 doesn't tell the bytes of a character apart, a byte below 0x30 is never part of a multibyte one.
+// The first of the next few bytes that the content decodes from up to such a byte is the boundary.
+fn gb18030_boundary(buff: &[u8]) -> usize {
+    let anchor = match buff.iter().take(4096).position(|b| *b < 0x30) {
+        Some(anchor) => anchor,
+        None => return 0,
+    };
+    (0..std::cmp::min(4, anchor))
+        .find(|start| !GB18030.decode_without_bom_handling(&buff[*start..anchor]).1)
+        .unwrap_or(0)
+}
+
+// Decodes content to UTF-8, the invalid bytes are shown as replacement characters.
+fn decode_content(buff: &[u8], encoding: &'static Encoding) -> String {
+    let (content, _) = encoding.decode_with_bom_removal(buff);
+    content.into_owned()
+}
+
+// Gets lines of a file, the line numbers start from 1.
+fn get_lines_render(path: &PathBuf, line_indexes: &LineIndexes, range: LineRange) -> Result<LinesRender, Box<dyn Error>> {
+    let max_file_len = 512000;         // the max returned size, default is 512kb.
+    let max_lines = 10000;             // the max returned lines
+    let encoding = detect_encoding(path);
+
+    line_indexes.with_index(path, |index, file, file_len| {
+        let total_lines = index.total_lines(file_len);
+        let (start_line, end_line) = match range {
+            LineRange::Head(n) => (1, n),
+            LineRange::Tail(n) => (std::cmp::max(total_lines.saturating_sub(n) + 1, 1), total_lines),
+            LineRange::Range(start, end) => (std::cmp::max(start, 1), end),
+        };
+        let end_line = std::cmp::min(std::cmp::min(end_line, total_lines), start_line + max_lines - 1);
+
+        let mut seek = index.offset_of_line(file, start_line - 1)?;
+        file.seek(SeekFrom::Start(seek))?;
+        let mut reader = BufReader::new(Read::by_ref(file).take(file_len - seek));
+        let mut content = vec![];
+        let mut line = start_line;
+        while line <= end_line && (content.len() as u64) < max_file_len {
+            let len = read_line(&mut reader, encoding, &mut content)?;
+            if len == 0 {
+                break;
+            }
+            seek += len as u64;
+            line += 1;
+        }
+
+        Ok(LinesRender::new(
+            decode_content(&content, encoding),
+            directory_filter(path.to_string_lossy().to_string()),
+            start_line,
+            line - 1,
+            total_lines,
+            seek,
+        ))
+    })
+}
+
+// Finds the file that a rotated log has been renamed to by its inode
+fn find_rotated_file(path: &PathBuf, inode: u64) -> Option<File> {
+    find_rotated_path(path, inode).and_then(|path| File::open(path).ok())
+}
+
+fn find_rotated_path(path: &PathBuf, inode: u64) -> Option<PathBuf> {
+    let dir = path.parent()?;
+    for entry in fs::read_dir(dir).ok()? {
+        if let Ok(entry) = entry {
+            if entry.ino() == inode {
+                return Some(entry.path());
+            }
+        }
+    }
+    None
+}
+
+// Finds the file a permalink was made for, the log may have been rotated or truncated since then.
+// `size` is how much of the file had been read when the link was made.
+// Returns the file to open, and a notice if the linked position may have moved.
+fn resolve_pinned_file(path: &PathBuf, inode: Option<u64>, size: Option<u64>) -> (PathBuf, Option<String>) {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return (path.clone(), None),
+    };
+    match inode {
+        Some(inode) if inode != metadata.ino() => match find_rotated_path(path, inode) {
+            Some(rotated) => {
+                let notice = format!("文件已被轮转，打开的是创建链接时的文件：{}", directory_filter(rotated.to_string_lossy().to_string()));
+                (rotated, Some(notice))
+            }
+            None => (path.clone(), Some("文件已被轮转且原文件已不存在，链接的位置可能已变化".to_owned())),
+        },
+        _ if size.map_or(false, |size| metadata.len() < size) => {
+            (path.clone(), Some("文件已被截断，链接的位置可能已变化".to_owned()))
+        }
+        _ => (path.clone(), None),
+    }
+}
+
+// Parses the lines of a permalink, such as `120` or `120-135`, lines are counted from 1.
+fn parse_line_span(value: &str) -> Option<(u64, u64)> {
+    let value = value.trim();
+    let (first, last) = match value.find('-') {
+        Some(pos) => (value[..pos].trim().parse().ok()?, value[pos + 1..].trim().parse().ok()?),
+        None => {
+            let line = value.parse().ok()?;
+            (line, line)
+        }
+    };
+    if first == 0 || last < first {
+        return None;
+    }
+    Some((first, last))
+}
+
+// Opens a file at the position of a permalink, with a few lines before the target as context.
+// The target lines are marked, an offset marks the line it is in.
+fn get_permalink_render(
+    path: &PathBuf,
+    offset: Option<u64>,
+    lines: Option<(u64, u64)>,
+    line_indexes: &LineIndexes,
+) -> Result<DetailRender, Box<dyn Error>> {
+    let context_lines = 20;
+    let (first, last) = match (lines, offset) {
+        (Some(lines), _) => lines,
+        (None, Some(offset)) => match line_indexes.line_of_offset(path, offset) {
+            Ok(line) => (line + 1, line + 1),
+            // Compressed files have no line index, they are opened at the offset
+            Err(_) => return get_window_render(path, offset),
+        },
+        (None, None) => return get_detail_render(path, 0, None, false),
+    };
+
+    let start_line = first.saturating_sub(context_lines + 1);
+    let start = line_indexes.with_index(path, |index, file, _| index.offset_of_line(file, start_line))?;
+    let mut render = get_window_render(path, start)?;
+    render.set_marked_lines(first, last);
+    Ok(render)
+}
+
+// Opens a file where the reader stopped last time, with a few lines before as context.
+// The line the unread content begins at is marked by a divider.
+fn get_unread_render(
+    path: &PathBuf,
+    cursor: Option<ReadCursor>,
+    line_indexes: &LineIndexes,
+) -> Result<DetailRender, Box<dyn Error>> {
+    let context_lines = 20;
+    let metadata = fs::metadata(path)?;
+    let (offset, notice) = match cursor {
+        Some(cursor) if cursor.inode == metadata.ino() && cursor.offset <= metadata.len() => (cursor.offset, None),
+        Some(_) => (0, Some("文件在上次阅读后已被轮转或截断，从头开始都是未读内容")),
+        None => {
+            let mut render = get_detail_render(path, 0, None, false)?;
+            render.set_notice("还没有这个文件的阅读记录".to_owned());
+            return Ok(render);
+        }
+    };
+
+    let line = line_indexes.line_of_offset(path, offset)?;
+    let mut render = match offset < metadata.len() {
+        true => {
+            let start = line_indexes.with_index(path, |index, file, _| {
+                index.offset_of_line(file, line.saturating_sub(context_lines))
+            })?;
+            get_window_render(path, start)?
+        }
+        // The divider is after the last line
+        false => get_detail_render(path, 0, None, false)?,
+    };
+    render.set_unread_line(line + 1);
+    if let Some(notice) = notice {
+        render.set_notice(notice.to_owned());
+    } else if offset >= metadata.len() {
+        render.set_notice("上次阅读后没有新内容".to_owned());
+    }
+    Ok(render)
+}
+
+// Reads at most `max_len` bytes from `seek`, returns the content and where it begins and ends.
+// The content is cut at the last complete line unless the end of the file is reached.
+fn read_window(
+    file: &mut File,
+    seek: u64,
+    max_len: u64,
+    encoding: &'static Encoding,
+) -> Result<(String, u64, u64), Box<dyn Error>> {
+    file.seek(SeekFrom::Start(seek))?;
+    let (contents, start, end, _) = read_window_from(file, seek, max_len, encoding)?;
+    Ok((contents, start, end))
+}
+
+// Reads a window like `read_window` from a reader positioned at `seek`, also tells whether the reader ran out.
+fn read_window_from<R: Read>(
+    reader: R,
+    seek: u64,
+    max_len: u64,
+    encoding: &'static Encoding,
+) -> Result<(String, u64, u64, bool), Box<dyn Error>> {
+    let mut buff = vec![];
+    reader.take(max_len).read_to_end(&mut buff)?;
+    let ended = (buff.len() as u64) < max_len;
+    if !ended {
+        if let Some(len) = line_ends(&buff, seek, encoding).last() {
+            buff.truncate(len);
+        }
+    }
+
+    let start = char_boundary(&buff, seek, encoding);
+    Ok((decode_content(&buff[start..], encoding), seek + start as u64, seek + buff.len() as u64, ended))
+}
+
+// Try to read the file from `seek`.
+fn attemp_to_read_file(
+    file: &mut File,
+    seek: u64,
+    encoding: &'static Encoding,
+) -> Result<(String, u64), Box<dyn Error>> {
+    let mut buff = vec![];
+    file.seek(SeekFrom::Start(seek))?;
+    file.read_to_end(&mut buff)?;
+    // That returned content that intercepted by Seek maybe is incomplete(multibyte encoding),so sets some offset 
+    let start = char_boundary(&buff, seek, encoding);
+    Ok((decode_content(&buff[start..], encoding), seek + start as u64))
+}
+
+// A file followed by the live tail, the file stays open so a rotated file can be drained.
+struct TailFile {
+    file: File,
+    path: PathBuf,
+    file_path: String,
+    seek: u64,
+    inode: u64,
+    encoding: &'static Encoding,
+}
+
+impl TailFile {
+    fn open(path: &PathBuf, seek: u64, inode: Option<u64>) -> Result<TailFile, Box<dyn Error>> {
+        let mut file = File::open(path)?;
+        let current_inode = file.metadata()?.ino();
+        let inode = match inode {
+            Some(inode) if inode != current_inode => {
+                // Rotated after the client has read it, continues with the old file
+                if let Some(old_file) = find_rotated_file(path, inode) {
+                    file = old_file;
+                }
+                inode
+            }
+            _ => current_inode,
+        };
+
+        Ok(TailFile {
+            file,
+            path: path.clone(),
+            file_path: directory_filter(path.to_string_lossy().to_string()),
+            seek,
+            inode,
+            encoding: detect_encoding(path),
+        })
+    }
+
+    // Reads what has been appended since `seek`, cut at the last complete line.
+    fn read_appended(&mut self) -> io::Result<Option<String>> {
+        self.read_chunk(false)
+    }
+
+    // Reads a chunk from `seek`, with `drain` an incomplete last line is read too.
+    fn read_chunk(&mut self, drain: bool) -> io::Result<Option<String>> {
+        let max_chunk_len = 65536;         // the max size of a single event, default is 64kb.
+        let file_len = self.file.metadata()?.len();
+        if file_len <= self.seek {
+            return Ok(None);
+        }
+
+        let mut buff = vec![];
+        self.file.seek(SeekFrom::Start(self.seek))?;
+        (&mut self.file).take(max_chunk_len).read_to_end(&mut buff)?;
+
+        let len = match line_ends(&buff, self.seek, self.encoding).last() {
+            _ if drain && (buff.len() as u64) < max_chunk_len => buff.len(),
+            Some(len) => len,
+            None if buff.len() as u64 == max_chunk_len && self.encoding == UTF_8 => match str::from_utf8(&buff) {
+                Ok(_) => buff.len(),
+                Err(e) => e.valid_up_to(),
+            },
+            None if buff.len() as u64 == max_chunk_len => buff.len(),
+            // Wait until the line is complete
+            None => return Ok(None),
+        };
+        buff.truncate(len);
+        self.seek += len as u64;
+
+        Ok(Some(decode_content(&buff, self.encoding)))
+    }
+
+    // Starts over from the new file after a rotation or truncation.
+    // The rest of a rotated-away file is drained first, a chunk per call under its own inode and seek.
+    fn reopen_if_rotated(&mut self) -> io::Result<Option<Rotation>> {
+        let metadata = match fs::metadata(&self.path) {
+            Ok(metadata) => metadata,
+            // The new file hasn't been created yet
+            Err(_) => return Ok(None),
+        };
+        let replaced = metadata.ino() != self.inode;
+        if !replaced && metadata.len() >= self.seek {
+            return Ok(None);
+        }
+
+        if replaced {
+            if self.file.metadata()?.ino() == self.inode {
+                if let Some(content) = self.read_chunk(true)? {
+                    return Ok(Some(Rotation::Drained(content)));
+                }
+            }
+            self.file = File::open(&self.path)?;
+        }
+
+        self.seek = 0;
+        self.inode = metadata.ino();
+        Ok(Some(Rotation::Reopened))
+    }
+
+    fn render(&self, content: String) -> DetailRender {
+        let mut render = DetailRender::new(content, self.file_path.clone(), self.seek);
+        render.set_inode(self.inode);
+        render
+    }
+}
+
+// What `TailFile::reopen_if_rotated` has done
+enum Rotation {
+    // Read a chunk of the rotated-away file, there may be more
+    Drained(String),
+    // Moved on to the new file
+    Reopened,
+}
+
+// How the content read by the live tail is pushed to the client.
+enum TailMode {
+    // The lines are classified by level and grouped into multi-line records
+    Content(LogView),
+    // The lines of all files are merged into timeline entries
+    Timeline(TimeParser),
+    // The lines are parsed into JSON records and filtered
+    Records(JsonFilter),
+}
+
+// Pushes the content appended to files as Server-Sent Events.
+// The watcher wakes us up as soon as a file grows, so clients don't need to poll `/more`.
+struct TailStream {
+    files: Vec<TailFile>,
+    mode: TailMode,
+    buffer: Vec<u8>,
+    flushed: bool,
+    events: Receiver<RawEvent>,
+    _watcher: RecommendedWatcher,
+    _slot: Slot,
+}
+
+impl TailStream {
+    fn new(files: Vec<TailFile>, mode: TailMode, slots: &Arc<Slots>) -> Result<TailStream, Box<dyn Error>> {
+        let slot = slots.try_acquire().ok_or("实时追踪的连接数已达上限，请稍后再试")?;
+        let (tx, rx) = channel();
+        let mut watcher = raw_watcher(tx)?;
+        let mut dirs: Vec<&Path> = files.iter().map(|f| f.path.parent().unwrap_or(f.path.as_path())).collect();
+        dirs.sort();
+        dirs.dedup();
+        for dir in dirs {
+            // Watches the directory, so a rotated file replaced by a new one is noticed as well
+            watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        }
+
+        Ok(TailStream {
+            files,
+            mode,
+            buffer: vec![],
+            flushed: true,
+            events: rx,
+            _watcher: watcher,
+            _slot: slot,
+        })
+    }
+
+    // Reads all files once, returns whether there was anything new.
+    fn read_files(&mut self) -> io::Result<bool> {
+        let mut entries = vec![];
+        let mut changed = false;
+
+        for i in 0..self.files.len() {
+            let mut contents = vec![];
+            let appended = match self.files[i].reopen_if_rotated()? {
+                Some(Rotation::Drained(drained)) => Some(drained),
+                Some(Rotation::Reopened) => {
+                    if let TailMode::Content(_) = self.mode {
+                        let mut render = self.files[i].render("".to_owned());
+                        render.set_rotated(true);
+                        self.push_render("rotate", render);
+                    }
+                    changed = true;
+                    self.files[i].read_appended()?
+                }
+                None => self.files[i].read_appended()?,
+            };
+
+            if let Some(content) = appended {
+                if let TailMode::Content(_) = self.mode {
+                    let render = self.files[i].render(content);
+                    self.push_render("append", render);
+                } else {
+                    contents.push(content);
+                }
+                changed = true;
+            }
+
+            let records = match &self.mode {
+                TailMode::Timeline(time_parser) => {
+                    for content in &contents {
+                        entries.extend(time_parser.parse_entries(content, i));
+                    }
+                    None
+                }
+                TailMode::Records(filter) => {
+                    Some(contents.iter().flat_map(|content| parse_records(content, filter)).collect())
+                }
+                TailMode::Content(_) => None,
+            };
+            if let Some(records) = records.filter(|records: &Vec<JsonRecord>| !records.is_empty()) {
+                let file = &self.files[i];
+                let render = JsonlRender::new(file.file_path.clone(), records, file.seek, file.inode);
+                let data = serde_json::to_string(&render).unwrap_or("{}".to_owned());
+                self.push_event("records", &data);
+            }
+        }
+
+        if !entries.is_empty() {
+            entries.sort_by_key(|e| e.timestamp);
+            let render = TimelineRender::new(self.timeline_sources(), entries);
+            let data = serde_json::to_string(&render).unwrap_or("{}".to_owned());
+            self.push_event("timeline", &data);
+        }
+        Ok(changed)
+    }
+
+    fn timeline_sources(&self) -> Vec<TimelineSource> {
+        self.files
+            .iter()
+            .map(|f| TimelineSource::new(f.file_path.clone(), f.seek, f.inode))
+            .collect()
+    }
+
+    fn push_render(&mut self, event: &str, mut render: DetailRender) {
+        if let TailMode::Content(view) = &self.mode {
+            view.annotate(&mut render);
+        }
+        let data = serde_json::to_string(&render).unwrap_or("{}".to_owned());
+        self.push_event(event, &data);
+    }
+
+    fn push_event(&mut self, event: &str, data: &str) {
+        push_sse_event(&mut self.buffer, event, data);
+        self.flushed = false;
+    }
+}
+
+// Writes a Server-Sent Event to `buffer`
+fn push_sse_event(buffer: &mut Vec<u8>, event: &str, data: &str) {
+    buffer.extend_from_slice(format!("event: {}\n", event).as_bytes());
+    for line in data.lines() {
+        buffer.extend_from_slice(format!("data: {}\n", line).as_bytes());
+    }
+    buffer.push(b'\n');
+}
+
+impl Read for TailStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let keep_alive = Duration::from_secs(15);
+        loop {
+            if !self.buffer.is_empty() {
+                let len = std::cmp::min(buf.len(), self.buffer.len());
+                buf[..len].copy_from_slice(&self.buffer[..len]);
+                self.buffer.drain(..len);
+                return Ok(len);
+            }
+
+            if !self.flushed {
+                // Asks rocket to flush the events written so far
+                self.flushed = true;
+                return Err(io::Error::new(io::ErrorKind::WouldBlock, "flush"));
+            }
+
+            if self.read_files()? {
+                continue;
+            }
+
+            match self.events.recv_timeout(keep_alive) {
+                Ok(_) => {}
+                Err(RecvTimeoutError::Timeout) => {
+                    // A comment line, lets a closed connection fail on write
+                    self.buffer.extend_from_slice(b": keep-alive\n\n");
+                    self.flushed = false;
+                }
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            }
+        }
+    }
+}
+
+// A timestamp with an offset in the local time of the server, the timestamps without one are taken as local times.
+// The files merged may be written in different time zones.
+fn local_time<Tz: TimeZone>(time: DateTime<Tz>) -> NaiveDateTime {
+    time.with_timezone(&Local).naive_local()
+}
+
+// Parses the timestamps at the beginning of log lines with the formats of `--time-format`.
+#[derive(Debug, Clone)]
+struct TimeParser {
+    formats: Vec<String>,
+}
+
+impl TimeParser {
+    fn new(formats: &[String]) -> TimeParser {
+        TimeParser {
+            formats: formats.to_vec(),
+        }
+    }
+
+    fn parse(&self, line: &str) -> Option<NaiveDateTime> {
+        self.parse_prefix(line).map(|(time, _)| time)
+    }
+
+    // Removes the leading timestamp of a line
+    fn strip<'a>(&self, line: &'a str) -> &'a str {
+        match self.parse_prefix(line) {
+            Some((_, len)) => &line[len..],
+            None => line,
+        }
+    }
+
+    // Parses the leading timestamp of a line, returns it with the length of the line it takes.
+    fn parse_prefix(&self, line: &str) -> Option<(NaiveDateTime, usize)> {
+        let trimmed = line.trim_start_matches(|c| c == '[' || c == ' ');
+        let offset = line.len() - trimmed.len();
+        // The length taken is measured on the trimmed line, the offset of the trimmed prefix is added once
+        let line = match trimmed.char_indices().nth(64) {
+            Some((pos, _)) => &trimmed[..pos],
+            None => trimmed,
+        };
+
+        // The timestamp is one of the first words, it may contain spaces
+        let mut ends: Vec<usize> = line.match_indices(' ').map(|(pos, _)| pos).take(3).collect();
+        ends.push(line.len());
+        for end in ends {
+            let candidate = line[..end].trim_end_matches(|c| c == ']' || c == ',' || c == ':' || c == '|');
+            if let Ok(time) = DateTime::parse_from_rfc3339(candidate) {
+                return Some((local_time(time), offset + end));
+            }
+            for format in &self.formats {
+                let time = match format.contains("%z") || format.contains("%:z") {
+                    true => DateTime::parse_from_str(candidate, format).map(local_time),
+                    false => NaiveDateTime::parse_from_str(candidate, format),
+                };
+                if let Ok(time) = time {
+                    return Some((time, offset + end));
+                }
+            }
+        }
+        None
+    }
+
+    // Splits content into entries, the lines without a timestamp belong to the entry before them.
+    fn parse_entries(&self, content: &str, source: usize) -> Vec<TimelineEntry> {
+        let mut entries: Vec<TimelineEntry> = vec![];
+        for line in content.lines() {
+            match (self.parse(line), entries.last_mut()) {
+                (None, Some(entry)) => {
+                    entry.content.push('\n');
+                    entry.content.push_str(line);
+                }
+                (timestamp, _) => entries.push(TimelineEntry::new(source, timestamp, line.to_owned())),
+            }
+        }
+
+        // The lines at the beginning go with the first timestamp
+        if let Some(timestamp) = entries.iter().find_map(|e| e.timestamp) {
+            for entry in entries.iter_mut().take_while(|e| e.timestamp.is_none()) {
+                entry.timestamp = Some(timestamp);
+            }
+        }
+        entries
+    }
+}
+
+// Tells the first line of a multi-line record, such as a log line followed by its stack trace.
+struct RecordSplitter {
+    patterns: Vec<Regex>,
+    time_parser: TimeParser,
+}
+
+impl RecordSplitter {
+    fn new(args: &Args) -> RecordSplitter {
+        RecordSplitter {
+            patterns: args.record_starts.clone(),
+            time_parser: TimeParser::new(&args.time_formats),
+        }
+    }
+
+    // Without patterns, a record starts with a timestamp
+    fn is_start(&self, line: &str) -> bool {
+        match self.patterns.is_empty() {
+            true => self.time_parser.parse(line).is_some(),
+            false => self.patterns.iter().any(|p| p.is_match(line)),
+        }
+    }
+
+    // Counts the lines of each record in content, the first count is for the lines before the first record.
+    // Returns nothing if no record spans several lines.
+    fn record_lines(&self, content: &str) -> Vec<usize> {
+        let mut counts = vec![0];
+        for line in content.lines() {
+            match self.is_start(line) {
+                true => counts.push(1),
+                false => *counts.last_mut().unwrap() += 1,
+            }
+        }
+        if counts.iter().skip(1).all(|count| *count <= 1) {
+            return vec![];
+        }
+        counts
+    }
+}
+
+// Recognizes the level of log lines, the levels are ordered from the most severe.
+struct LevelClassifier {
+    levels: Vec<(String, Regex)>,
+}
+
+impl LevelClassifier {
+    fn new(args: &Args) -> LevelClassifier {
+        LevelClassifier {
+            levels: args.levels.clone(),
+        }
+    }
+
+    fn index_of(&self, name: &str) -> Option<usize> {
+        self.levels.iter().position(|(level, _)| level.eq_ignore_ascii_case(name.trim()))
+    }
+
+    // The level found first in the line, the level is near the beginning
+    fn classify(&self, line: &str) -> Option<usize> {
+        let head = match line.char_indices().nth(200) {
+            Some((pos, _)) => &line[..pos],
+            None => line,
+        };
+        self.levels
+            .iter()
+            .enumerate()
+            .filter_map(|(i, (_, pattern))| pattern.find(head).map(|m| (m.start(), i)))
+            .min()
+            .map(|(_, i)| i)
+    }
+
+    // Sets the level of each line and the counts of the levels.
+    // With `min_level`, only the lines at this level or more severe are kept.
+    fn annotate(&self, render: &mut DetailRender, min_level: Option<usize>) {
+        let mut current = None;
+        let mut counts = vec![0; self.levels.len()];
+        let mut content = String::new();
+        let mut line_levels = vec![];
+        for line in render.content.split_inclusive('\n') {
+            if let Some(level) = self.classify(line) {
+                current = Some(level);
+                counts[level] += 1;
+            }
+            // The lines without a level, such as a stack trace, take the level of the line before them
+            if min_level.map_or(true, |min| current.map_or(false, |level| level <= min)) {
+                if min_level.is_some() {
+                    content.push_str(line);
+                }
+                line_levels.push(current.map_or_else(String::new, |level| self.levels[level].0.clone()));
+            }
+        }
+
+        if min_level.is_some() {
+            render.set_content(content);
+        }
+        let names = self.levels.iter().map(|(name, _)| name.clone()).collect();
+        render.set_levels(names, counts, line_levels);
+    }
+}
+
+// How the lines of a log are shown: classified by level, filtered, then grouped into records.
+struct LogView {
+    splitter: RecordSplitter,
+    classifier: LevelClassifier,
+    level: String,
+    min_level: Option<usize>,
+}
+
+impl LogView {
+    fn new(args: &Args, level: Option<String>) -> LogView {
+        let classifier = LevelClassifier::new(args);
+        let min_level = level.and_then(|name| classifier.index_of(&name));
+        // The name as configured, unknown levels don't filter
+        let level = min_level.map_or_else(String::new, |i| classifier.levels[i].0.clone());
+        LogView {
+            splitter: RecordSplitter::new(args),
+            classifier,
+            level,
+            min_level,
+        }
+    }
+
+    fn annotate(&self, render: &mut DetailRender) {
+        self.classifier.annotate(render, self.min_level);
+        render.set_record_lines(self.splitter.record_lines(&render.content));
+        render.set_level(self.level.clone());
+    }
+}
+
+// A filter on the fields of JSON records, such as `level=error AND status>=500 OR msg~timeout`.
+// AND binds tighter than OR, nested fields are separated by dots.
+struct JsonFilter {
+    groups: Vec<Vec<JsonCondition>>,
+}
+
+struct JsonCondition {
+    field: String,
+    op: String,
+    value: String,
+}
+
+impl JsonFilter {
+    fn parse(filter: &str) -> Result<JsonFilter, Box<dyn Error>> {
+        let or = Regex::new(r"(?i)\s+OR\s+")?;
+        let and = Regex::new(r"(?i)\s+AND\s+")?;
+        let condition = Regex::new(r"^\s*([\w.\-]+)\s*(!=|>=|<=|=|>|<|~)\s*(.*?)\s*$")?;
+        let mut groups = vec![];
+        let filter = filter.trim();
+        if filter.is_empty() {
+            return Ok(JsonFilter { groups });
+        }
+
+        for group in or.split(filter) {
+            let mut conditions = vec![];
+            for c in and.split(group) {
+                let captures = condition
+                    .captures(c)
+                    .ok_or(format!("过滤条件格式错误：{}", c))?;
+                conditions.push(JsonCondition {
+                    field: captures[1].to_owned(),
+                    op: captures[2].to_owned(),
+                    value: captures[3].trim_matches(|c| c == '"' || c == '\'').to_owned(),
+                });
+            }
+            groups.push(conditions);
+        }
+        Ok(JsonFilter { groups })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    fn matches(&self, record: &JsonRecord) -> bool {
+        match &record.fields {
+            Some(fields) => self.is_empty() || self.groups.iter().any(|group| group.iter().all(|c| c.matches(fields))),
+            None => true,
+        }
+    }
+}
+
+impl JsonCondition {
+    fn matches(&self, fields: &Value) -> bool {
+        let value = self.field.split('.').try_fold(fields, |value, key| {
+            value.get(key).or_else(|| key.parse::<usize>().ok().and_then(|i| value.get(i)))
+        });
+        let value = match value {
+            Some(value) => value,
+            None => return self.op == "!=",
+        };
+        let text = match value {
+            Value::String(s) => s.clone(),
+            value => value.to_string(),
+        };
+
+        match self.op.as_str() {
+            "=" => text.eq_ignore_ascii_case(&self.value),
+            "!=" => !text.eq_ignore_ascii_case(&self.value),
+            "~" => text.to_lowercase().contains(&self.value.to_lowercase()),
+            op => {
+                // Compares as numbers if both sides are, otherwise as strings, e.g. ISO timestamps
+                let ordering = match (value.as_f64().or_else(|| text.parse().ok()), self.value.parse::<f64>()) {
+                    (Some(a), Ok(b)) => a.partial_cmp(&b),
+                    _ => Some(text.as_str().cmp(self.value.as_str())),
+                };
+                ordering.map_or(false, |o| match op {
+                    ">" => o == Ordering::Greater,
+                    ">=" => o != Ordering::Less,
+                    "<" => o == Ordering::Less,
+                    _ => o != Ordering::Greater,
+                })
+            }
+        }
+    }
+}
+
+// Searches whole records instead of lines with context, a match on any line returns the full record.
+// The output is formatted like the searcher's, `:` follows the number of the matched lines and `-` of the others.
+fn search_records(
+    path: &PathBuf,
+    search: &str,
+    case_insensitive: bool,
+    splitter: &RecordSplitter,
+    size_limit: usize,
+) -> Result<String, Box<dyn Error>> {
+    let max_record_lines = 1000;        // a longer record is split, in case the lines don't match the record start
+    let matcher = RegexBuilder::new(search).case_insensitive(case_insensitive).build()?;
+    let encoding = detect_encoding(path);
+    let mut reader = BufReader::new(open_reader(path)?);
+    let mut output = String::new();
+    let mut record: Vec<(u64, String)> = vec![];
+    let mut matched = false;
+    let mut line = vec![];
+    let mut line_number = 0;
+
+    loop {
+        line.clear();
+        let text = match read_line(&mut reader, encoding, &mut line)? {
+            0 => None,
+            _ => Some(decode_content(&line, encoding)),
+        };
+        let ends = match &text {
+            Some(text) => splitter.is_start(text) || record.len() >= max_record_lines,
+            None => true,
+        };
+        if ends {
+            if matched {
+                if !output.is_empty() {
+                    output.push_str("--\n");
+                }
+                for (number, text) in &record {
+                    let separator = if matcher.is_match(text) { ':' } else { '-' };
+                    output.push_str(&format!("{}{}{}\n", number, separator, text));
+                }
+                if output.len() > size_limit {
+                    return Err("搜索结果太大，请使用更准确的搜索词")?;
+                }
+            }
+            record.clear();
+            matched = false;
+        }
+
+        match text {
+            Some(text) => {
+                line_number += 1;
+                let text = text.trim_end_matches(|c| c == '\r' || c == '\n').to_owned();
+                matched = matched || matcher.is_match(&text);
+                record.push((line_number, text));
+            }
+            None => break,
+        }
+    }
+    Ok(output)
+}
+
+// Gets the filtered content of a file
+fn get_search_render(
+    path: &PathBuf,
+    search: &str,
+    before: &str,
+    after: &str,
+    case_insensitive: bool,
+    splitter: Option<&RecordSplitter>,
+) -> Result<SearchRender, Box<dyn Error>> {
+    let mut content = "".to_string();
+    let is_root = format!("{:?}", path).to_string() == "\"/home/smoothsea/project/108new/./application\"".to_string();
+    
+    let archive_files = match path.is_dir() {
+        true => None,
+        false => get_archive_files(path)?,
+    };
+    if path.is_dir() || archive_files.is_some() {
+        let in_archive = archive_files.is_some();
+        let children = match archive_files {
+            Some(files) => files,
+            None => {
+                let mut paths = vec![];
+                for entry in fs::read_dir(path)?.filter(|en| {
+                    !en.as_ref().unwrap().path().file_name().unwrap().to_str().unwrap().starts_with(".")
+                }) {
+                    paths.push(entry?.path());
+                }
+                paths
+            }
+        };
+
+        for path in children {
+            // The members of an archive are files, so the archive isn't listed again for each of them
+            let ret = match in_archive {
+                true => search_file(&path, search, before, after, case_insensitive, splitter),
+                false => get_search_render(&path, search, before, after, case_insensitive, splitter)
+                    .map(|render| render.content),
+            };
+
+            match ret {
+                Ok(render_content) => {
+                    if render_content.len() > 0 {
+                        if path.is_dir() || is_archive(&path) {
+                            content = format!("{}{}", content, render_content);
+                        } else {
+                            content = format!(
+                                "{}\r\n\r\n\r\n\r\n{}\r\n\r\n{}",
+                                content,
+                                directory_filter(path.to_str().unwrap().to_string()),
+                                render_content
+                            );
+                        }
+                    }
+                },
+                Err(e) => {
+                    content = format!(
+                        "{}\r\n\r\n\r\n\r\n{}\r\n\r\n{}",
+                        content,
+                        directory_filter(path.to_str().unwrap().to_string()),
+                        e.as_ref(),
+                    );
+                }
+            }
+        }
+    } else {
+        content = search_file(path, search, before, after, case_insensitive, splitter)?;
+    }
+
+    Ok(SearchRender::new(
+        content,
+        path.to_string_lossy().to_string(),
+        search.to_string(),
+    ))
+}
+
+// Collects the matched lines and their context lines of a search.
+// Only the number of the matched lines is kept if `keep` is false.
+struct HitSink<'a, M> {
+    matcher: &'a M,
+    exact_offsets: bool,
+    keep: bool,
+    limit: usize,
+    count: usize,
+    binary: bool,
+    hits: Vec<SearchHit>,
+    before: Vec<SearchLine>,
+}
+
+impl<'a, M: Matcher> HitSink<'a, M> {
+    fn new(matcher: &'a M, options: &SearchOptions, encoding: &'static Encoding, limit: usize) -> HitSink<'a, M> {
+        let limit = match options.mode {
+            SearchMode::Lines => options.max_count.map_or(limit, |max_count| std::cmp::min(max_count, limit)),
+            SearchMode::Count => options.max_count.unwrap_or(usize::MAX),
+            SearchMode::Files => 1,
+        };
+        HitSink {
+            matcher,
+            exact_offsets: encoding == UTF_8,
+            keep: options.mode == SearchMode::Lines,
+            limit,
+            count: 0,
+            binary: false,
+            hits: vec![],
+            before: vec![],
+        }
+    }
+
+    fn finish(self) -> FileHits {
+        FileHits {
+            hits: self.hits,
+            count: self.count,
+            binary: self.binary,
+        }
+    }
+
+    fn line(&self, line: Option<u64>, offset: u64, bytes: &[u8]) -> SearchLine {
+        SearchLine {
+            line: line.unwrap_or(0),
+            offset: if self.exact_offsets { Some(offset) } else { None },
+            text: String::from_utf8_lossy(bytes).trim_end_matches(|c| c == '\r' || c == '\n').to_owned(),
+        }
+    }
+}
+
+impl<'a, M: Matcher> Sink for HitSink<'a, M> {
+    type Error = io::Error;
+
+    fn matched(&mut self, _searcher: &Searcher, mat: &SinkMatch) -> Result<bool, io::Error> {
+        self.count += 1;
+        if !self.keep {
+            return Ok(self.count < self.limit);
+        }
+        let line = self.line(mat.line_number(), mat.absolute_byte_offset(), mat.bytes());
+        let mut spans = vec![];
+        self.matcher
+            .find_iter(line.text.as_bytes(), |m| {
+                // A line matched only by NOT patterns has nothing to highlight
+                if !m.is_empty() {
+                    spans.push((m.start(), m.end()));
+                }
+                true
+            })
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+
+        self.hits.push(SearchHit {
+            line: line.line,
+            offset: line.offset,
+            text: line.text,
+            spans,
+            before: std::mem::take(&mut self.before),
+            after: vec![],
+        });
+        Ok(self.count < self.limit)
+    }
+
+    // A line shared by two matches is given once, as the after context of the first one
+    fn context(&mut self, _searcher: &Searcher, context: &SinkContext) -> Result<bool, io::Error> {
+        let line = self.line(context.line_number(), context.absolute_byte_offset(), context.bytes());
+        match (context.kind(), self.hits.last_mut()) {
+            (SinkContextKind::After, Some(hit)) => hit.after.push(line),
+            _ => self.before.push(line),
+        }
+        Ok(true)
+    }
+
+    // The search of a binary file in UTF-16 stops at its first NUL byte
+    fn binary_data(&mut self, _searcher: &Searcher, _binary_byte_offset: u64) -> Result<bool, io::Error> {
+        self.binary = true;
+        Ok(false)
+    }
+}
+
+// The results of a search in a file. `count` is the number of the matched lines, which may be more than
+// the hits, and a binary file that is skipped has the ones before its first NUL byte only.
+#[derive(Debug, Default)]
+struct FileHits {
+    hits: Vec<SearchHit>,
+    count: usize,
+    binary: bool,
+}
+
+// Matches the lines that satisfy patterns combined with AND, OR and NOT, such as `timeout AND NOT retry OR panic`.
+// AND binds tighter than OR, a NOT pattern only excludes lines. The matches in a line are of its patterns.
+#[derive(Debug, Clone)]
+struct BooleanMatcher {
+    groups: Vec<Vec<(bool, RegexMatcher)>>,
+}
+
+impl BooleanMatcher {
+    fn new(search: &str, options: &SearchOptions) -> Result<BooleanMatcher, Box<dyn Error>> {
+        let or = Regex::new(r"(?i)\s+OR\s+")?;
+        let and = Regex::new(r"(?i)\s+AND\s+")?;
+        let not = Regex::new(r"(?i)^NOT\s+")?;
+        let mut groups = vec![];
+        for group in or.split(search.trim()) {
+            let mut terms = vec![];
+            for term in and.split(group) {
+                let term = term.trim();
+                let negated = not.is_match(term);
+                let pattern = not.replace(term, "");
+                if pattern.is_empty() {
+                    return Err(format!("搜索条件格式错误：{}", group).into());
+                }
+                let pattern = match options.fixed_strings {
+                    true => regex::escape(&pattern),
+                    false => pattern.into_owned(),
+                };
+                // Built as the single pattern search is, a word may begin or end with a non-word character
+                let regex = RegexMatcherBuilder::new()
+                    .case_insensitive(options.case_insensitive)
+                    .word(options.word)
+                    .multi_line(true)
+                    .build(&pattern)?;
+                terms.push((negated, regex));
+            }
+            groups.push(terms);
+        }
+        Ok(BooleanMatcher { groups })
+    }
+
+    // The first match at or after `at` in the line from `start` to `end`, a line matched only by NOT patterns
+    // is matched as a whole
+    fn find_in_line(&self, haystack: &[u8], start: usize, end: usize, at: usize) -> Option<Match> {
+        let line = &haystack[start..end];
+        let from = std::cmp::max(start, at);
+        self.groups
+            .iter()
+            .filter(|group| group.iter().all(|(negated, regex)| regex.is_match(line).unwrap_or(false) != *negated))
+            .filter_map(|group| {
+                let mut positive = group.iter().filter(|(negated, _)| !negated).peekable();
+                if positive.peek().is_none() {
+                    return if start >= at { Some(Match::new(start, start)) } else { None };
+                }
+                positive
+                    .filter_map(|(_, regex)| regex.find_at(&haystack[..end], from).ok().flatten())
+                    .min_by_key(|m| (m.start(), std::cmp::Reverse(m.end())))
+            })
+            .min_by_key(|m| (m.start(), std::cmp::Reverse(m.end())))
+    }
+}
+
+impl Matcher for BooleanMatcher {
+    type Captures = NoCaptures;
+    type Error = NoError;
+
+    fn find_at(&self, haystack: &[u8], at: usize) -> Result<Option<Match>, NoError> {
+        let mut start = haystack[..at].iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+        while start < haystack.len() {
+            let end = haystack[start..]
+                .iter()
+                .position(|&b| b == b'\n')
+                .map_or(haystack.len(), |i| start + i);
+            if let Some(m) = self.find_in_line(haystack, start, end, at) {
+                return Ok(Some(m));
+            }
+            start = end + 1;
+        }
+        Ok(None)
+    }
+
+    fn new_captures(&self) -> Result<NoCaptures, NoError> {
+        Ok(NoCaptures::new())
+    }
+}
+
+// The matcher of a single pattern, a fixed string is escaped and `word` matches whole words only
+fn build_regex_matcher(search: &str, options: &SearchOptions) -> Result<RegexMatcher, Box<dyn Error>> {
+    let pattern = match options.fixed_strings {
+        true => regex::escape(search),
+        false => search.to_owned(),
+    };
+    Ok(RegexMatcherBuilder::new()
+        .case_insensitive(options.case_insensitive)
+        .word(options.word)
+        .build(&pattern)?)
+}
+
+// Gets at most `limit` matched lines of a file, or counts them at most `max_count` with the other modes
+fn search_hits<M: Matcher>(
+    path: &PathBuf,
+    matcher: &M,
+    options: &SearchOptions,
+    limit: usize,
+) -> Result<FileHits, Box<dyn Error>> {
+    if is_compressed(path) {
+        return search_reader_hits(path, open_reader(path)?, matcher, options, limit);
+    }
+    let encoding = detect_encoding(path);
+    search_content_hits(File::open(path)?, encoding, matcher, options, limit)
+}
+
+// The same with the content of the file read from `reader`
+fn search_reader_hits<M: Matcher, R: Read>(
+    path: &PathBuf,
+    reader: R,
+    matcher: &M,
+    options: &SearchOptions,
+    limit: usize,
+) -> Result<FileHits, Box<dyn Error>> {
+    let (sample, reader) = sample_reader(reader)?;
+    let encoding = detect_encoding_with(path, &sample);
+    search_content_hits(reader, encoding, matcher, options, limit)
+}
+
+// Searches content in `encoding`. When binary files are skipped, one is searched up to its first NUL byte.
+// The text of UTF-16 has NUL bytes, they are looked for after transcoding it then, which may leave out
+// the matches in the buffer the NUL byte is found in.
+fn search_content_hits<M: Matcher, R: Read>(
+    reader: R,
+    encoding: &'static Encoding,
+    matcher: &M,
+    options: &SearchOptions,
+    limit: usize,
+) -> Result<FileHits, Box<dyn Error>> {
+    let mut sink = HitSink::new(matcher, options, encoding, limit);
+    let mut searcher = hit_searcher(options, encoding)?;
+    if options.skip_binary && !is_utf16(encoding) {
+        let mut reader = TextPrefix { reader, binary: false };
+        searcher.search_reader(matcher, &mut reader, &mut sink)?;
+        sink.binary |= reader.binary;
+    } else {
+        searcher.search_reader(matcher, reader, &mut sink)?;
+    }
+    Ok(sink.finish())
+}
+
+// Reads up to the first NUL byte, which ends the text of a binary file
+struct TextPrefix<R> {
+    reader: R,
+    binary: bool,
+}
+
+impl<R: Read> Read for TextPrefix<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.binary {
+            return Ok(0);
+        }
+        let len = self.reader.read(buf)?;
+        match buf[..len].iter().position(|b| *b == 0) {
+            Some(pos) => {
+                self.binary = true;
+                Ok(pos)
+            }
+            None => Ok(len),
+        }
+    }
+}
+
+fn hit_searcher(options: &SearchOptions, encoding: &'static Encoding) -> Result<Searcher, Box<dyn Error>> {
+    let binary_detection = match options.skip_binary && is_utf16(encoding) {
+        true => BinaryDetection::quit(b'\x00'),
+        false => BinaryDetection::none(),
+    };
+    let mut search_build = SearcherBuilder::new();
+    search_build
+        .line_number(true)
+        .invert_match(options.invert)
+        .binary_detection(binary_detection)
+        .before_context(options.before)
+        .after_context(options.after);
+    if encoding != UTF_8 {
+        // Transcodes the file to UTF-8 before searching, the offsets are of the transcoded content then
+        search_build.encoding(Some(grep::searcher::Encoding::new(encoding.name())?));
+    }
+    Ok(search_build.build())
+}
+
+// Searches the members of an archive, or of a directory in it, in one pass over the archive.
+// The globs are matched with the members as with the files of a directory, the member given as `path` is always
+// searched. The members share `limit`.
+fn search_archive_hits<M: Matcher>(
+    root: &Path,
+    path: &PathBuf,
+    matcher: &M,
+    options: &SearchOptions,
+    limit: usize,
+) -> Vec<(PathBuf, Result<FileHits, String>)> {
+    let (archive, prefix) = match archive_location(path) {
+        Some(location) => location,
+        None => return vec![],
+    };
+    let prefix_dir = format!("{}/", prefix);
+    let mut results = vec![];
+    let mut limit = limit;
+    let read = for_each_archive_member(&archive, |entry, reader| {
+        if !(prefix.is_empty() || entry.name == prefix || entry.name.starts_with(&prefix_dir)) {
+            return true;
+        }
+        let member = archive.join(&entry.name);
+        let relative = member.strip_prefix(root).unwrap_or(&member);
+        let filter = &options.filter;
+        if member != *path && !(filter.includes(relative) && !filter.excludes(relative) && filter.allows_member(entry)) {
+            return true;
+        }
+        let hits = match (decompress(&member, reader), member == *path) {
+            // A member asked for by its path is searched as text
+            (Ok(reader), true) => {
+                let options = SearchOptions { skip_binary: false, ..options.clone() };
+                search_reader_hits(&member, reader, matcher, &options, limit).map_err(|e| e.to_string())
+            }
+            (Ok(reader), false) => search_reader_hits(&member, reader, matcher, options, limit).map_err(|e| e.to_string()),
+            (Err(e), _) => Err(e.to_string()),
+        };
+        if let Ok(hits) = &hits {
+            limit = limit.saturating_sub(hits.hits.len());
+        }
+        results.push((member, hits));
+        true
+    });
+    if let Err(e) = read {
+        results.push((path.clone(), Err(e.to_string())));
+    }
+    results
+}
+
+// Finds the files to search under a directory or in an archive and gives them to `on_file` as they are found,
+// a file given as `path` is always searched. A directory is walked by `threads` threads, the hidden files,
+// the ones ignored by `.ignore` files in the tree and the ones left out by the filter are skipped.
+// A directory that can't be read is given with the error. The walk stops when `on_file` returns false.
+fn walk_search_files<F>(path: &PathBuf, filter: &FileFilter, threads: usize, on_file: F)
+where
+    F: Fn(PathBuf, Option<String>) -> bool + Clone + Send + 'static,
+{
+    if !path.is_dir() {
+        give_search_file(path, path, 0, filter, &on_file);
+        return;
+    }
+
+    let (root, exclude) = (path.clone(), filter.clone());
+    let mut builder = WalkBuilder::new(path);
+    builder
+        .threads(threads)
+        .follow_links(true)
+        .parents(false)
+        .git_ignore(false)
+        .git_global(false)
+        .git_exclude(false)
+        .max_depth(filter.max_depth)
+        .filter_entry(move |entry| {
+            let relative = entry.path().strip_prefix(&root).unwrap_or_else(|_| entry.path());
+            entry.depth() == 0 || !exclude.excludes(relative)
+        });
+    builder.build_parallel().run(|| {
+        let (root, filter, on_file) = (path.clone(), filter.clone(), on_file.clone());
+        Box::new(move |entry| {
+            let going = match entry {
+                Ok(entry) if entry.file_type().map_or(true, |file_type| file_type.is_dir()) => true,
+                Ok(entry) => give_search_file(&root, &entry.path().to_path_buf(), entry.depth(), &filter, &on_file),
+                Err(e) => on_file(root.clone(), Some(e.to_string())),
+            };
+            match going {
+                true => WalkState::Continue,
+                false => WalkState::Quit,
+            }
+        })
+    });
+}
+
+// Gives a file found by the walk. An archive is given as a whole, its members are matched with the globs
+// as the files of a directory when it is searched. Returns what `on_file` returns.
+fn give_search_file<F>(root: &Path, path: &PathBuf, depth: usize, filter: &FileFilter, on_file: &F) -> bool
+where
+    F: Fn(PathBuf, Option<String>) -> bool,
+{
+    if depth == 0 {
+        return on_file(path.clone(), None);
+    }
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) => return on_file(path.clone(), Some(e.to_string())),
+    };
+    // The members of an archive are filtered as it is searched
+    let allowed = match is_archive(path) {
+        true => filter.allows_archive(&metadata),
+        false => filter.allows_metadata(&metadata) && filter.includes(path.strip_prefix(root).unwrap_or(path)),
+    };
+    match allowed {
+        true => on_file(path.clone(), None),
+        false => true,
+    }
+}
+
+// Searches a file, a directory or an archive, the files are searched by `options.threads` workers as the walk finds them,
+// the files searched at the same time by all searches are at most `--search-threads`.
+// `on_file` is called after each file in the order the files are found, with the results if anything is found,
+// the search stops when it returns false.
+fn search_each_file<F>(
+    path: &PathBuf,
+    search: &str,
+    options: &SearchOptions,
+    on_file: F,
+) -> Result<SearchProgress, Box<dyn Error>>
+where
+    F: FnMut(Option<SearchFile>, &SearchProgress) -> bool,
+{
+    match options.boolean {
+        true => search_files_with(BooleanMatcher::new(search, options)?, path, options, on_file),
+        false => search_files_with(build_regex_matcher(search, options)?, path, options, on_file),
+    }
+}
+
+fn search_files_with<M, F>(
+    matcher: M,
+    path: &PathBuf,
+    options: &SearchOptions,
+    mut on_file: F,
+) -> Result<SearchProgress, Box<dyn Error>>
+where
+    M: Matcher + Clone + Send + 'static,
+    F: FnMut(Option<SearchFile>, &SearchProgress) -> bool,
+{
+    let max_hits: usize = 10000;
+    let mut progress = SearchProgress::default();
+    let found = Arc::new(AtomicUsize::new(0));
+    let stopped = Arc::new(AtomicBool::new(false));
+    let (file_sender, files) = channel();
+    {
+        let (path, filter, threads) = (path.clone(), options.filter.clone(), options.threads);
+        let (found, stopped) = (found.clone(), stopped.clone());
+        thread::spawn(move || {
+            walk_search_files(&path, &filter, threads, move |file, error| {
+                found.fetch_add(1, AtomicOrdering::Relaxed);
+                !stopped.load(AtomicOrdering::Relaxed) && file_sender.send((file, error)).is_ok()
+            });
+        });
+    }
+
+    // The files are numbered in the order they are taken, an archive gives the results of its members together
+    let queue = Arc::new(Mutex::new((files, 0)));
+    // The matched lines given so far in the order of the files, a file before them in the order
+    // is always left enough of the limit, so a truncated result is a prefix of the files
+    let emitted = Arc::new(AtomicUsize::new(0));
+    let (sender, results) = channel();
+    for _ in 0..options.threads {
+        let (queue, emitted, stopped, sender) = (queue.clone(), emitted.clone(), stopped.clone(), sender.clone());
+        let (matcher, options, root) = (matcher.clone(), options.clone(), path.clone());
+        // A file asked for by its path is searched as text even if it looks binary
+        let text_options = SearchOptions { skip_binary: false, ..options.clone() };
+        thread::spawn(move || loop {
+            if stopped.load(AtomicOrdering::Relaxed) {
+                break;
+            }
+            let next = {
+                let mut queue = queue.lock().unwrap();
+                let (files, count) = &mut *queue;
+                files.recv().ok().map(|file| {
+                    *count += 1;
+                    (*count - 1, file)
+                })
+            };
+            let (index, (file, error)) = match next {
+                Some(next) => next,
+                None => break,
+            };
+            let _slot = options.slots.acquire();
+            let limit = max_hits.saturating_sub(emitted.load(AtomicOrdering::Relaxed));
+            let hits = match error {
+                Some(error) => vec![(file, Err(error))],
+                None if archive_location(&file).is_some() => search_archive_hits(&root, &file, &matcher, &options, limit),
+                None => {
+                    let options = if file == root { &text_options } else { &options };
+                    let hits = search_hits(&file, &matcher, options, limit).map_err(|e| e.to_string());
+                    vec![(file, hits)]
+                }
+            };
+            if sender.send((index, hits)).is_err() {
+                break;
+            }
+        });
+    }
+    drop(sender);
+
+    // The workers finish the files in any order, the results are given in the order of the files.
+    // `kept` is the number of the matched lines given, they are only counted with the other modes.
+    // The files to search are the ones found but not taken yet and the ones searched, the members of an archive included.
+    let mut kept = 0;
+    let mut taken = 0;
+    let mut pending = BTreeMap::new();
+    'results: for (index, files) in results {
+        pending.insert(index, files);
+        while let Some(files) = pending.remove(&taken) {
+            taken += 1;
+            for (file, hits) in files {
+                progress.scanned += 1;
+                progress.files = (found.load(AtomicOrdering::Relaxed) + progress.scanned).saturating_sub(taken);
+                let file_path = directory_filter(file.to_string_lossy().to_string());
+                if let Ok(FileHits { binary: true, .. }) = &hits {
+                    progress.binary += 1;
+                }
+                let result = match hits {
+                    Ok(hits) if hits.count == 0 => None,
+                    Ok(FileHits { mut hits, mut count, .. }) => {
+                        if options.mode == SearchMode::Lines {
+                            // A file searched before the files ahead of it were given may have found too many
+                            hits.truncate(max_hits.saturating_sub(kept));
+                            count = hits.len();
+                            kept += count;
+                            emitted.store(kept, AtomicOrdering::Relaxed);
+                        }
+                        progress.matches += count;
+                        Some(SearchFile {
+                            path: file_path,
+                            hits,
+                            count,
+                            error: None,
+                        })
+                    }
+                    Err(error) => Some(SearchFile {
+                        path: file_path,
+                        hits: vec![],
+                        count: 0,
+                        error: Some(error),
+                    }),
+                };
+                if !on_file(result, &progress) || kept >= max_hits {
+                    stopped.store(true, AtomicOrdering::Relaxed);
+                    break 'results;
+                }
+            }
+        }
+    }
+    progress.files = (found.load(AtomicOrdering::Relaxed) + progress.scanned).saturating_sub(taken);
+    progress.truncated = kept >= max_hits;
+    Ok(progress)
+}
+
+// Searches a file, a directory or an archive, the results are given by file and by matched line
+fn get_search_json_render(path: &PathBuf, search: &str, options: &SearchOptions) -> Result<SearchJsonRender, Box<dyn Error>> {
+    let mut files = vec![];
+    let progress = search_each_file(path, search, options, |file, _| {
+        files.extend(file);
+        true
+    })?;
+
+    Ok(SearchJsonRender {
+        search: search.to_owned(),
+        files,
+        truncated: progress.truncated,
+    })
+}
+
+// Sends the results of a search as Server-Sent Events while it goes on, so a big directory doesn't stall the page.
+// A `file` event has the results of a file, a `progress` event the counts so far, and `done` or `error` ends it.
+// The search stops when the client goes away.
+// The response holds a worker like the live tail, so it takes a slot of the same streams.
+struct SearchStream {
+    events: Receiver<(&'static str, String)>,
+    buffer: Vec<u8>,
+    flushed: bool,
+    _slot: Slot,
+}
+
+impl SearchStream {
+    fn start(path: PathBuf, search: String, options: SearchOptions, slots: &Arc<Slots>) -> Result<SearchStream, Box<dyn Error>> {
+        let slot = slots.try_acquire().ok_or("流式连接数已达上限，请稍后再试")?;
+        let progress_interval = Duration::from_millis(500);
+        let (sender, events) = sync_channel(16);
+        thread::spawn(move || {
+            let mut last_progress = Instant::now();
+            let result = search_each_file(&path, &search, &options, |file, progress| {
+                let sent = match file {
+                    Some(file) => {
+                        let event = SearchFileEvent {
+                            file: &file,
+                            progress,
+                        };
+                        sender.send(("file", serde_json::to_string(&event).unwrap_or_default()))
+                    }
+                    // The files without matches may be many, their counts are sent every now and then
+                    None if last_progress.elapsed() >= progress_interval => {
+                        last_progress = Instant::now();
+                        sender.send(("progress", serde_json::to_string(progress).unwrap_or_default()))
+                    }
+                    None => Ok(()),
+                };
+                sent.is_ok()
+            });
+            let _ = match result {
+                Ok(progress) => sender.send(("done", serde_json::to_string(&progress).unwrap_or_default())),
+                Err(e) => sender.send(("error", serde_json::to_string(&e.to_string()).unwrap_or_default())),
+            };
+        });
+
+        Ok(SearchStream {
+            events,
+            buffer: vec![],
+            flushed: true,
+            _slot: slot,
+        })
+    }
+}
+
+impl Read for SearchStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let keep_alive = Duration::from_secs(15);
+        loop {
+            if !self.buffer.is_empty() {
+                let len = std::cmp::min(buf.len(), self.buffer.len());
+                buf[..len].copy_from_slice(&self.buffer[..len]);
+                self.buffer.drain(..len);
+                return Ok(len);
+            }
+
+            if !self.flushed {
+                // Asks rocket to flush the events written so far
+                self.flushed = true;
+                return Err(io::Error::new(io::ErrorKind::WouldBlock, "flush"));
+            }
+
+            match self.events.recv_timeout(keep_alive) {
+                Ok((event, data)) => push_sse_event(&mut self.buffer, event, &data),
+                // A large file may take long, a comment line keeps the connection
+                Err(RecvTimeoutError::Timeout) => self.buffer.extend_from_slice(b": keep-alive\n\n"),
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            }
+            self.flushed = false;
+        }
+    }
+}
+
+// Gets the filtered content of a single file
+fn search_file(
+    path: &PathBuf,
+    search: &str,
+    before: &str,
+    after: &str,
+    case_insensitive: bool,
+    splitter: Option<&RecordSplitter>,
+) -> Result<String, Box<dyn Error>> {
+    let size_limit = 10485760;   // The max size of filtered result per page
+    let content;
+
+    if let Some(splitter) = splitter {
+        content = search_records(path, search, case_insensitive, splitter, size_limit)?;
+    } else {
+        let mut matcher = RegexMatcherBuilder::new();
+        matcher.case_insensitive(case_insensitive);
+        let matcher = matcher.build(search)?;
+        let mut search_build = SearcherBuilder::new();
+        let mut printer = Standard::new_no_color(vec![]);
+        let before_num: usize = before.parse()?;
+        let after_num: usize = after.parse()?;
+        search_build.after_context(after_num);
+        search_build.before_context(before_num);
+        let encoding = detect_encoding(path);
+        if encoding != UTF_8 {
+            // Transcodes the file to UTF-8 before searching
+            search_build.encoding(Some(grep::searcher::Encoding::new(encoding.name())?));
+        }
+        if is_compressed(path) {
+            // Searches line by line, multi line mode would read the whole decompressed file into memory
+            search_build
+                .build()
+                .search_reader(&matcher, open_reader(path)?, printer.sink(&matcher))?;
+        } else {
+            let file = File::open(path)?;
+            search_build.multi_line(true);
+            search_build
+                .build()
+                .search_file(&matcher, &file, printer.sink(&matcher))?;
+        }
+        let search_bytes = printer.into_inner().into_inner();
+        content = String::from_utf8_lossy(&search_bytes).to_string();
+
+        if content.len() > size_limit {
+            return Err("搜索结果太大，请使用更准确的搜索词")?;
+        }
+    }
+    Ok(content)
+}
+
+// Where to look for matches in a file
+#[derive(Debug, Clone, Copy)]
+enum FindTarget {
+    // The matches at or after an offset
+    From(u64),
+    // The matches before an offset, the nearest ones
+    Before(u64),
+    // The matches from the nth one
+    Nth(u64),
+}
+
+// Collects the matches of a search, the search stops once they are found unless all matches are counted
+struct FindSink<'a> {
+    matcher: &'a RegexMatcher,
+    target: FindTarget,
+    limit: usize,
+    count: bool,
+    index: u64,
+    matches: VecDeque<FindMatch>,
+}
+
+impl<'a> FindSink<'a> {
+    fn found(&self) -> bool {
+        match self.target {
+            FindTarget::From(_) | FindTarget::Nth(_) => self.matches.len() >= self.limit,
+            // Later matches would be after the offset
+            FindTarget::Before(_) => false,
+        }
+    }
+
+    // Takes the matches of a line by their offsets and lengths in the file, tells whether to go on
+    fn add(&mut self, line: u64, found: Vec<(u64, u64)>) -> bool {
+        let mut passed = false;
+        for (offset, length) in found {
+            self.index += 1;
+            let find_match = FindMatch {
+                index: self.index,
+                offset,
+                length,
+                line,
+            };
+            match self.target {
+                FindTarget::From(from) if offset >= from && !self.found() => self.matches.push_back(find_match),
+                FindTarget::Nth(nth) if self.index >= nth && !self.found() => self.matches.push_back(find_match),
+                FindTarget::Before(before) if offset < before => {
+                    self.matches.push_back(find_match);
+                    if self.matches.len() > self.limit {
+                        self.matches.pop_front();
+                    }
+                }
+                FindTarget::Before(_) => passed = true,
+                _ => {}
+            }
+        }
+        self.count || !(passed || self.found())
+    }
+}
+
+impl<'a> Sink for FindSink<'a> {
+    type Error = io::Error;
+
+    fn matched(&mut self, _searcher: &Searcher, mat: &SinkMatch) -> Result<bool, io::Error> {
+        let line_offset = mat.absolute_byte_offset();
+        let mut found = vec![];
+        self.matcher
+            .find_iter(mat.bytes(), |m| {
+                found.push((line_offset + m.start() as u64, (m.end() - m.start()) as u64));
+                true
+            })
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+        Ok(self.add(mat.line_number().unwrap_or(0), found))
+    }
+}
+
+// Finds the matches in a file that isn't UTF-8, each line is decoded and the matches are mapped back to the bytes of the file.
+fn find_decoded<R: Read>(
+    reader: R,
+    encoding: &'static Encoding,
+    matcher: &RegexMatcher,
+    sink: &mut FindSink,
+) -> Result<(), Box<dyn Error>> {
+    let mut reader = BufReader::new(reader);
+    let mut line = vec![];
+    let mut offset = 0;
+    let mut number = 0;
+    loop {
+        line.clear();
+        let len = read_line(&mut reader, encoding, &mut line)?;
+        if len == 0 {
+            break;
+        }
+        number += 1;
+        // The BOM at the beginning of the file isn't text
+        let bom = match offset {
+            0 => Encoding::for_bom(&line).map_or(0, |(_, bom)| bom),
+            _ => 0,
+        };
+        let raw = &line[bom..];
+        let (text, _) = encoding.decode_without_bom_handling(raw);
+        let mut spans = vec![];
+        matcher.find_iter(text.as_bytes(), |m| {
+            spans.push((m.start(), m.end()));
+            true
+        })?;
+
+        if !spans.is_empty() {
+            let bounds = decoded_bounds(raw, encoding);
+            let raw_of = |pos: usize| bounds[bounds.partition_point(|(decoded, _)| *decoded <= pos) - 1].1 as u64;
+            let line_offset = offset + bom as u64;
+            let found = spans
+                .into_iter()
+                .map(|(start, end)| (line_offset + raw_of(start), raw_of(end) - raw_of(start)))
+                .collect();
+            if !sink.add(number, found) {
+                break;
+            }
+        }
+        offset += len as u64;
+    }
+    Ok(())
+}
+
+// Where the characters decoded from a line end, as pairs of the lengths of the decoded text and of the bytes read.
+fn decoded_bounds(raw: &[u8], encoding: &'static Encoding) -> Vec<(usize, usize)> {
+    let mut decoder = encoding.new_decoder_without_bom_handling();
+    let mut text = String::new();
+    let mut bounds = vec![(0, 0)];
+    for i in 0..raw.len() {
+        text.reserve(decoder.max_utf8_buffer_length(1).unwrap_or(16));
+        decoder.decode_to_string(&raw[i..i + 1], &mut text, false);
+        if text.len() > bounds[bounds.len() - 1].0 {
+            bounds.push((text.len(), i + 1));
+        }
+    }
+    bounds
+}
+
+// Finds the matches of `search` in the whole file, not only the window in the viewer.
+// With `count`, all the matches are counted for the total.
+fn get_find_render(
+    path: &PathBuf,
+    search: &str,
+    target: FindTarget,
+    limit: usize,
+    case_insensitive: bool,
+    count: bool,
+) -> Result<FindRender, Box<dyn Error>> {
+    let encoding = detect_encoding(path);
+    let matcher = RegexMatcherBuilder::new().case_insensitive(case_insensitive).build(search)?;
+    let mut sink = FindSink {
+        matcher: &matcher,
+        target,
+        limit: std::cmp::max(limit, 1),
+        count,
+        index: 0,
+        matches: VecDeque::new(),
+    };
+    // The offsets are of the bytes in the file, so a UTF-8 file isn't transcoded
+    let mut searcher = SearcherBuilder::new().line_number(true).bom_sniffing(false).build();
+    if encoding != UTF_8 {
+        find_decoded(open_reader(path)?, encoding, &matcher, &mut sink)?;
+    } else if is_compressed(path) {
+        searcher.search_reader(&matcher, open_reader(path)?, &mut sink)?;
+    } else {
+        searcher.search_path(&matcher, path, &mut sink)?;
+    }
+
+    let total = match count {
+        true => Some(sink.index),
+        false => None,
+    };
+    Ok(FindRender::new(
+        directory_filter(path.to_string_lossy().to_string()),
+        search.to_owned(),
+        sink.matches.into_iter().collect(),
+        total,
+    ))
+}
+
+fn is_auth(cookies: &mut Cookies, config: &Args) -> bool {
+    let config = config.to_owned().clone();
+    let username = cookies
+        .get_private("username")
+        .map(|value| format!("{}", value));
+    config.username.map(|value| format!("username={}", value)) == username
+}
+
+#[get("/")]
+fn auth(args: State<Args>, mut cookies: Cookies) -> Redirect {
+    if is_auth(&mut cookies, &*args) {
+        Redirect::to("/file-reader-index")
+    } else {
+        Redirect::to("/login")
+    }
+}
+
+#[get("/file-reader-index")]
+fn index(args: State<Args>, conditions: RequestConditions, _auth: Authorization) -> Cached<Template> {
+    match get_directory_info_render(&args.file_dir) {
+        Ok(mut render) => {
+            if args.log {
+                log!("Access index");
+            }
+            render.set_write(args.write);
+            let etag = listing_etag(&render);
+            return Cached::new(Template::render("index", render), Some(etag), &conditions);
+        }
+        Err(e) => {
+            let render = ErrorRender::new(e.to_string());
+            return Cached::new(Template::render("error", render), None, &conditions);
+        }
+    };
+}
+
+#[get("/login")]
+fn login() -> Template {
+    Template::render("login", ErrorRender::new("".to_owned()))
+}
+
+#[get("/debug")]
+fn debug(args: State<Args>) -> Template {
+    if args.log {
+        log!("Access debug");
+    }
+    Template::render("debug", ErrorRender::new("".to_owned()))
+}
+
+#[post("/login", data = "<login>")]
 fn do_login(args: State<Args>, login: Form<Login>, mut cookies: Cookies) -> String {
     let args = (*args).clone();
     let mut render: HashMap<String, String> = HashMap::new();
@@ -443,60 +4992,460 @@ fn do_login(args: State<Args>, login: Form<Login>, mut cookies: Cookies) -> Stri
     serde_json::to_string(&render).unwrap_or(return_result(0, ""))
 }
 
-#[get("/more?<seek>&<path>", rank = 3)]
-fn more(args: State<Args>, seek: u64, path: String, _auth: Authorization) -> String {
-    let mut output = "".to_string();
-    let path = &args.file_dir.join(path_to_relative(&PathBuf::from(path)));
-    match get_detail_render(&path, seek) {
-        Ok(render) => {
-            if let Ok(a) = serde_json::to_string(&render) {
-                output = a;
-            }
-        }
-        Err(e) => {
-            output = e.to_string();
-        }
+#[get("/more?<seek>&<path>&<inode>&<drain>&<level>", rank = 3)]
+fn more(
+    args: State<Args>,
+    seek: u64,
+    path: String,
+    inode: Option<u64>,
+    drain: Option<bool>,
+    level: Option<String>,
+    _auth: Authorization,
+) -> String {
+    let mut output = "".to_string();
+    let path = &args.file_dir.join(path_to_relative(&PathBuf::from(path)));
+    match get_detail_render(&path, seek, inode, drain.unwrap_or(true)) {
+        Ok(mut render) => {
+            LogView::new(&args, level).annotate(&mut render);
+            if let Ok(a) = serde_json::to_string(&render) {
+                output = a;
+            }
+        }
+        Err(e) => {
+            output = e.to_string();
+        }
+    }
+    output
+}
+
+#[get("/previous?<seek>&<path>&<level>", rank = 3)]
+fn previous(
+    args: State<Args>,
+    line_indexes: State<LineIndexes>,
+    seek: u64,
+    path: String,
+    level: Option<String>,
+    _auth: Authorization,
+) -> String {
+    let mut output = "".to_string();
+    let path = &args.file_dir.join(path_to_relative(&PathBuf::from(path)));
+    match get_previous_render(&path, seek) {
+        Ok(mut render) => {
+            LogView::new(&args, level).annotate(&mut render);
+            if !render.compressed {
+                if let Some(line) = line_indexes.line_of_offset_if_ready(&path, render.start_seek) {
+                    render.set_start_line(line + 1);
+                }
+            }
+            if let Ok(a) = serde_json::to_string(&render) {
+                output = a;
+            }
+        }
+        Err(e) => {
+            output = e.to_string();
+        }
+    }
+    output
+}
+
+#[derive(Deserialize, Debug)]
+struct CursorParams {
+    path: String,
+    seek: u64,
+    inode: u64,
+}
+
+// Records how far the reader has viewed a file
+#[post("/cursor", data = "<params>")]
+fn cursor(
+    args: State<Args>,
+    cursors: State<ReadCursors>,
+    reader: Reader,
+    params: Json<CursorParams>,
+    _auth: Authorization,
+) -> String {
+    if args.log {
+        log!(format!("Update read cursor, path:{}", params.path));
+    }
+    let key = cursor_key(&PathBuf::from(&params.path));
+    let cursor = ReadCursor {
+        inode: params.inode,
+        offset: params.seek,
+    };
+    cursors.update(&reader.0, &key, cursor);
+    return_result(1, "")
+}
+
+#[get("/lines?<path>&<start>&<end>&<head>&<tail>", rank = 3)]
+fn lines(
+    args: State<Args>,
+    line_indexes: State<LineIndexes>,
+    path: String,
+    start: Option<u64>,
+    end: Option<u64>,
+    head: Option<u64>,
+    tail: Option<u64>,
+    _auth: Authorization,
+) -> String {
+    let range = match (head, tail, start) {
+        (Some(n), _, _) => LineRange::Head(n),
+        (_, Some(n), _) => LineRange::Tail(n),
+        (_, _, Some(start)) => LineRange::Range(start, end.unwrap_or(start)),
+        _ => return return_result(0, "缺少行号参数"),
+    };
+    let path = &args.file_dir.join(path_to_relative(&PathBuf::from(path)));
+    match get_lines_render(&path, &line_indexes, range) {
+        Ok(render) => serde_json::to_string(&render).unwrap_or(return_result(0, "")),
+        Err(e) => return_result(0, &e.to_string()),
+    }
+}
+
+#[get("/tail?<seek>&<path>&<inode>&<level>", rank = 3)]
+fn tail(
+    args: State<Args>,
+    seek: u64,
+    path: String,
+    inode: Option<u64>,
+    level: Option<String>,
+    _auth: Authorization,
+) -> Result<Content<Stream<TailStream>>, String> {
+    if args.log {
+        log!(format!("Access tail, path:{}", path));
+    }
+    let path = &args.file_dir.join(path_to_relative(&PathBuf::from(path)));
+    if is_compressed(&path) {
+        return Err("压缩文件不会增长，不支持实时追踪".to_owned());
+    }
+    match TailFile::open(&path, seek, inode).and_then(|file| TailStream::new(vec![file], TailMode::Content(LogView::new(&args, level)), &args.stream_slots)) {
+        Ok(stream) => Ok(Content(ContentType::new("text", "event-stream"), Stream::from(stream))),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// The files are given as repeated `file` parameters, or matched by `glob` under the directory `path`
+#[get("/timeline?<path>&<glob>&<files..>", rank = 3)]
+fn timeline(
+    args: State<Args>,
+    path: Option<String>,
+    glob: Option<String>,
+    files: TimelineFiles,
+    _auth: Authorization,
+) -> Template {
+    if args.log {
+        log!(format!("Access timeline, files:{:?}, glob:{:?}", files.0, glob));
+    }
+    let dir = &args.file_dir.join(path_to_relative(&PathBuf::from(path.unwrap_or_default())));
+    let time_parser = TimeParser::new(&args.time_formats);
+    let render = get_timeline_files(&args.file_dir, &dir, &files.0, &glob)
+        .and_then(|paths| get_timeline_render(&paths, &time_parser));
+    match render {
+        Ok(render) => Template::render("timeline", render),
+        Err(e) => Template::render("error", ErrorRender::new(e.to_string())),
+    }
+}
+
+#[get("/timeline_tail?<timeline..>", rank = 3)]
+fn timeline_tail(
+    args: State<Args>,
+    timeline: TimelineQuery,
+    _auth: Authorization,
+) -> Result<Content<Stream<TailStream>>, String> {
+    if args.log {
+        log!(format!("Access timeline tail, files:{:?}", timeline.paths));
+    }
+    if timeline.paths.len() > MAX_TIMELINE_FILES {
+        return Err(format!("文件太多，最多合并{}个文件", MAX_TIMELINE_FILES));
+    }
+    let mut tail_files = vec![];
+    for (i, file) in timeline.paths.iter().enumerate() {
+        let path = args.file_dir.join(path_to_relative(&PathBuf::from(file)));
+        if is_compressed(&path) {
+            return Err("压缩文件不会增长，不支持实时追踪".to_owned());
+        }
+        let seek = timeline.seeks.get(i).cloned().unwrap_or(0);
+        let inode = timeline.inodes.get(i).cloned().filter(|inode| *inode > 0);
+        match TailFile::open(&path, seek, inode) {
+            Ok(file) => tail_files.push(file),
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+
+    let time_parser = TimeParser::new(&args.time_formats);
+    match TailStream::new(tail_files, TailMode::Timeline(time_parser), &args.stream_slots) {
+        Ok(stream) => Ok(Content(ContentType::new("text", "event-stream"), Stream::from(stream))),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[get("/jsonl?<path>&<filter>", rank = 3)]
+fn jsonl(args: State<Args>, path: String, filter: Option<String>, _auth: Authorization) -> Template {
+    if args.log {
+        log!(format!("Access jsonl, path:{}, filter:{:?}", path, filter));
+    }
+    let filter = filter.unwrap_or_default();
+    let path = &args.file_dir.join(path_to_relative(&PathBuf::from(path)));
+    let render = JsonFilter::parse(&filter).and_then(|f| get_jsonl_render(&path, 0, None, &f));
+    match render {
+        Ok(render) => {
+            let data = serde_json::to_string(&render).unwrap_or("{}".to_owned());
+            let page = JsonlPage::new(render.file_path, filter, is_compressed(&path), data);
+            Template::render("jsonl", page)
+        }
+        Err(e) => Template::render("error", ErrorRender::new(e.to_string())),
+    }
+}
+
+#[get("/jsonl_more?<path>&<seek>&<inode>&<filter>", rank = 3)]
+fn jsonl_more(
+    args: State<Args>,
+    path: String,
+    seek: u64,
+    inode: Option<u64>,
+    filter: Option<String>,
+    _auth: Authorization,
+) -> String {
+    if args.log {
+        log!(format!("Access jsonl more, path:{}, seek:{}, filter:{:?}", path, seek, filter));
+    }
+    let path = &args.file_dir.join(path_to_relative(&PathBuf::from(path)));
+    let render = JsonFilter::parse(&filter.unwrap_or_default())
+        .and_then(|f| get_jsonl_render(&path, seek, inode, &f));
+    match render {
+        Ok(render) => serde_json::to_string(&render).unwrap_or(return_result(0, "")),
+        Err(e) => return_result(0, &e.to_string()),
+    }
+}
+
+#[get("/jsonl_tail?<path>&<seek>&<inode>&<filter>", rank = 3)]
+fn jsonl_tail(
+    args: State<Args>,
+    path: String,
+    seek: u64,
+    inode: Option<u64>,
+    filter: Option<String>,
+    _auth: Authorization,
+) -> Result<Content<Stream<TailStream>>, String> {
+    if args.log {
+        log!(format!("Access jsonl tail, path:{}, seek:{}, filter:{:?}", path, seek, filter));
+    }
+    let path = &args.file_dir.join(path_to_relative(&PathBuf::from(path)));
+    if is_compressed(&path) {
+        return Err("压缩文件不会增长，不支持实时追踪".to_owned());
+    }
+    let stream = JsonFilter::parse(&filter.unwrap_or_default()).and_then(|filter| {
+        TailFile::open(&path, seek, inode).and_then(|file| TailStream::new(vec![file], TailMode::Records(filter), &args.stream_slots))
+    });
+    match stream {
+        Ok(stream) => Ok(Content(ContentType::new("text", "event-stream"), Stream::from(stream))),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[get("/diff?<left>&<right>&<left_range>&<right_range>&<side_by_side>&<ignore_time>", rank = 3)]
+fn diff(
+    args: State<Args>,
+    line_indexes: State<LineIndexes>,
+    left: String,
+    right: Option<String>,
+    left_range: Option<String>,
+    right_range: Option<String>,
+    side_by_side: bool,
+    ignore_time: bool,
+    _auth: Authorization,
+) -> Template {
+    if args.log {
+        log!(format!("Access diff, left:{}, right:{:?}", left, right));
+    }
+    // Compares two parts of the same file without a right path
+    let right = right.filter(|right| !right.trim().is_empty()).unwrap_or_else(|| left.clone());
+    let (left_range, right_range) = (left_range.unwrap_or_default(), right_range.unwrap_or_default());
+    let left_path = &args.file_dir.join(path_to_relative(&PathBuf::from(&left)));
+    let right_path = &args.file_dir.join(path_to_relative(&PathBuf::from(&right)));
+    let time_parser = TimeParser::new(&args.time_formats);
+    let time_parser = if ignore_time { Some(&time_parser) } else { None };
+
+    let rows = DiffRange::parse(&left_range).and_then(|l| {
+        DiffRange::parse(&right_range).and_then(|r| {
+            get_diff_rows((&left_path, &l), (&right_path, &r), side_by_side, time_parser, &line_indexes)
+        })
+    });
+    match rows {
+        Ok(rows) => {
+            let mut render = DiffRender::new(left, left_range, right, right_range, rows);
+            render.set_side_by_side(side_by_side);
+            render.set_ignore_time(ignore_time);
+            Template::render("diff", render)
+        }
+        Err(e) => Template::render("error", ErrorRender::new(e.to_string())),
+    }
+}
+
+#[get("/search?<record>&<query..>", rank = 3)]
+fn search(args: State<Args>, record: bool, query: LenientForm<SearchQuery>, _auth: Authorization) -> Template {
+    if args.log {
+        log!(format!("Access search, path: {}, search: {}", query.path, query.search));
+    }
+    let case_insensitive = match query.case_sensitive {
+        true => false,
+        false => true
+    };
+    let path = &args.file_dir.join(path_to_relative(&PathBuf::from(&query.path)));
+    if !record {
+        // The page gets the results from the JSON search
+        let mut render = SearchRender::new("".to_owned(), path.to_string_lossy().to_string(), query.search.clone());
+        render.set_json(true);
+        return Template::render("search", render);
+    }
+    // Whole records are searched line by line in a single file, without the filters and options of the line search
+    if query.has_options() {
+        return Template::render("error", ErrorRender::new("按记录搜索不支持文件过滤和搜索选项".to_owned()));
+    }
+    let before = query.before.clone().unwrap_or_else(|| "0".to_owned());
+    let after = query.after.clone().unwrap_or_else(|| "0".to_owned());
+    let splitter = RecordSplitter::new(&args);
+    match get_search_render(&path, &query.search, &before, &after, case_insensitive, Some(&splitter)) {
+        Ok(render) => {
+            return Template::render("search", render);
+        }
+        Err(e) => {
+            let render = ErrorRender::new(e.to_string());
+            return Template::render("error", render);
+        }
+    };
+}
+
+#[get("/search_json?<query..>", rank = 3)]
+fn search_json(args: State<Args>, query: LenientForm<SearchQuery>, _auth: Authorization) -> String {
+    if args.log {
+        log!(format!("Access search json, path: {}, search: {}", query.path, query.search));
+    }
+    let options = match SearchOptions::new(&args, &query) {
+        Ok(options) => options,
+        Err(e) => return return_result(0, &e.to_string()),
+    };
+    let path = &args.file_dir.join(path_to_relative(&PathBuf::from(&query.path)));
+    match get_search_json_render(&path, &query.search, &options) {
+        Ok(render) => serde_json::to_string(&render).unwrap_or(return_result(0, "")),
+        Err(e) => return_result(0, &e.to_string()),
+    }
+}
+
+#[get("/search_stream?<query..>", rank = 3)]
+fn search_stream(
+    args: State<Args>,
+    query: LenientForm<SearchQuery>,
+    _auth: Authorization,
+) -> Result<Content<Stream<SearchStream>>, String> {
+    if args.log {
+        log!(format!("Access search stream, path: {}, search: {}", query.path, query.search));
+    }
+    let options = SearchOptions::new(&args, &query).map_err(|e| e.to_string())?;
+    let path = args.file_dir.join(path_to_relative(&PathBuf::from(&query.path)));
+    match SearchStream::start(path, query.into_inner().search, options, &args.stream_slots) {
+        Ok(stream) => Ok(Content(ContentType::new("text", "event-stream"), Stream::from(stream))),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[get("/find?<path>&<search>&<from>&<backward>&<nth>&<limit>&<case_sensitive>&<total>", rank = 3)]
+fn find(
+    args: State<Args>,
+    path: String,
+    search: String,
+    from: Option<u64>,
+    backward: bool,
+    nth: Option<u64>,
+    limit: Option<usize>,
+    case_sensitive: bool,
+    total: bool,
+    _auth: Authorization,
+) -> String {
+    if args.log {
+        log!(format!("Access find, path: {}, search: {}", path, search));
+    }
+    let max_limit = 100;
+    let target = match (nth, backward) {
+        (Some(nth), _) => FindTarget::Nth(nth),
+        (None, true) => FindTarget::Before(from.unwrap_or(u64::MAX)),
+        (None, false) => FindTarget::From(from.unwrap_or(0)),
+    };
+    let limit = std::cmp::min(limit.unwrap_or(1), max_limit);
+    let path = &args.file_dir.join(path_to_relative(&PathBuf::from(path)));
+    match get_find_render(&path, &search, target, limit, !case_sensitive, total) {
+        Ok(render) => serde_json::to_string(&render).unwrap_or(return_result(0, "")),
+        Err(e) => return_result(0, &e.to_string()),
+    }
+}
+
+#[get("/hex?<seek>&<path>", rank = 3)]
+fn hex(args: State<Args>, seek: Option<String>, path: String, _auth: Authorization) -> Template {
+    if args.log {
+        log!(format!("Access hex, path:{}", path));
+    }
+    let path = &args.file_dir.join(path_to_relative(&PathBuf::from(path)));
+    let seek = seek.filter(|seek| !seek.trim().is_empty()).map_or(Ok(0), |seek| parse_offset(&seek));
+    match seek.and_then(|seek| get_hex_render(&path, seek)) {
+        Ok(render) => Template::render("hex", render),
+        Err(e) => Template::render("error", ErrorRender::new(e.to_string())),
+    }
+}
+
+#[derive(Debug, Responder)]
+enum DetailResponse {
+    Template(Template),
+    Listing(Cached<Template>),
+    File(Option<FileResponse>),
+    Member(Option<Stream<MemberReader>>),
+}
+
+// Sends a file as is, a member of an archive is decompressed on the fly
+fn download_response(path: &PathBuf, conditions: &RequestConditions) -> DetailResponse {
+    match split_archive_path(path) {
+        Some((archive, member)) => DetailResponse::Member(open_archive_member(&archive, &member).ok().map(Stream::from)),
+        None => DetailResponse::File(FileResponse::open(path, conditions).ok()),
     }
-    output
 }
 
-#[get("/search?<search>&<path>&<before>&<after>&<case_sensitive>", rank = 3)]
-fn search(
+#[get("/preview?<path>&<page>&<sort>&<desc>", rank = 3)]
+fn preview(
     args: State<Args>,
-    search: String,
     path: String,
-    before: String,
-    after: String,
-    case_sensitive: bool,
+    page: Option<usize>,
+    sort: Option<usize>,
+    desc: bool,
     _auth: Authorization,
 ) -> Template {
     if args.log {
-        log!(format!("Access search, path: {}, search: {}", path, search));
+        log!(format!("Access preview, path:{}", path));
     }
-    let case_insensitive = match case_sensitive {
-        true => false,
-        false => true
-    };
     let path = &args.file_dir.join(path_to_relative(&PathBuf::from(path)));
-    match get_search_render(&path, &search, &before, &after, case_insensitive) {
-        Ok(render) => {
-            return Template::render("search", render);
-        }
-        Err(e) => {
-            let render = ErrorRender::new(e.to_string());
-            return Template::render("error", render);
-        }
-    };
-}
-
-#[derive(Debug, Responder)]
-enum DetailResponse {
-    Template(Template),
-    NamedFile(Option<NamedFile>),
+    let language = detect_language(&path, &args);
+    match get_preview_render(&path, &language, page.unwrap_or(1), sort, desc) {
+        Ok(render) => Template::render("preview", render),
+        Err(e) => Template::render("error", ErrorRender::new(e.to_string())),
+    }
 }
 
-#[get("/<name..>?<download>", rank = 100)]
-fn detail(args: State<Args>, name: PathBuf, download: Option<u8>, _auth: Authorization) -> DetailResponse {
+#[get("/<name..>?<download>&<time>&<raw>&<level>&<offset>&<lines>&<inode>&<size>&<unread>", rank = 100)]
+fn detail(
+    args: State<Args>,
+    line_indexes: State<LineIndexes>,
+    highlighter: State<Highlighter>,
+    cursors: State<ReadCursors>,
+    reader: Reader,
+    name: PathBuf,
+    download: Option<u8>,
+    time: Option<String>,
+    raw: Option<u8>,
+    level: Option<String>,
+    offset: Option<u64>,
+    lines: Option<String>,
+    inode: Option<u64>,
+    size: Option<u64>,
+    unread: Option<u8>,
+    conditions: RequestConditions,
+    _auth: Authorization,
+) -> DetailResponse {
     if args.log {
         log!(format!("Access detail, path:{}", name.to_string_lossy()));
     }
@@ -505,7 +5454,8 @@ fn detail(args: State<Args>, name: PathBuf, download: Option<u8>, _auth: Authori
         match get_directory_info_render(&path) {
             Ok(mut render) => {
                 render.set_write(args.write);
-                return DetailResponse::Template(Template::render("index", render));
+                let etag = listing_etag(&render);
+                return DetailResponse::Listing(Cached::new(Template::render("index", render), Some(etag), &conditions));
             },
             Err(e) => {
                 let render = ErrorRender::new(e.to_string());
@@ -514,17 +5464,107 @@ fn detail(args: State<Args>, name: PathBuf, download: Option<u8>, _auth: Authori
         };
     } else {
         if download.is_some() {
-            return DetailResponse::NamedFile(NamedFile::open(&path).ok());
+            return download_response(&path, &conditions);
         }
 
-        match get_detail_render(&path, 0) {
+        // A compressed file or a member of an archive is decompressed once for the detections and the content,
+        // a path in an archive that isn't a member is a directory in it
+        let mut opened = match is_compressed(&path) && !is_archive(&path) {
+            true => open_sampled(&path).ok(),
+            false => None,
+        };
+        if opened.is_none() {
+            match get_archive_directory_render(&path) {
+                // The members of an archive are read only, nothing can be written
+                Ok(Some(render)) => {
+                    let etag = listing_etag(&render);
+                    return DetailResponse::Listing(Cached::new(Template::render("index", render), Some(etag), &conditions));
+                }
+                Ok(None) => {}
+                Err(e) => return DetailResponse::Template(Template::render("error", ErrorRender::new(e.to_string()))),
+            }
+        }
+        let sample = match &opened {
+            Some((sample, _)) => sample.clone(),
+            None => read_sample(&path),
+        };
+
+        if is_binary_with(&path, &sample) {
+            match get_hex_render(&path, 0) {
+                Ok(render) => return DetailResponse::Template(Template::render("hex", render)),
+                Err(e) => return DetailResponse::Template(Template::render("error", ErrorRender::new(e.to_string()))),
+            }
+        }
+
+        let language = detect_language_with(&path, &args, &sample);
+        let permalink = offset.is_some() || lines.is_some();
+        if raw.is_none() && time.is_none() && !permalink && unread.is_none() && is_previewable(&language) {
+            // Falls back to the raw content if the file can't be rendered
+            if let Ok(render) = get_preview_render(&path, &language, 1, None, false) {
+                return DetailResponse::Template(Template::render("preview", render));
+            }
+        }
+
+        let line_span = match &lines {
+            Some(value) => match parse_line_span(value) {
+                Some(span) => Some(span),
+                None => return DetailResponse::Template(Template::render("error", ErrorRender::new("行号范围错误".to_owned()))),
+            },
+            None => None,
+        };
+        let (path, notice) = match permalink {
+            true => resolve_pinned_file(&path, inode, size),
+            false => (path.clone(), None),
+        };
+        let path = &path;
+
+        let jump = time.is_some() || permalink || unread.is_some();
+        let render = match time {
+            Some(time) => match parse_jump_time(&time, &path) {
+                Some(time) => find_time_offset(&path, time, &TimeParser::new(&args.time_formats))
+                    .and_then(|offset| get_window_render(&path, offset)),
+                None => Err("时间格式错误".into()),
+            },
+            None if permalink => get_permalink_render(&path, offset, line_span, &line_indexes),
+            None if unread.is_some() => {
+                get_unread_render(&path, cursors.get(&reader.0, &cursor_key(&name)), &line_indexes)
+            }
+            None => match opened.take() {
+                Some((sample, reader)) => read_compressed_detail_render(&path, &sample, reader, 0),
+                None => get_detail_render(&path, 0, None, false),
+            },
+        };
+
+        match render {
             Ok(mut render) => {
                 render.set_write(args.write);
+                render.set_jsonl(is_jsonl_with(&path, &sample));
+                render.set_preview(is_previewable(&language));
+                if let Some(notice) = notice {
+                    render.set_notice(notice);
+                }
+                if !is_source_code(&language) {
+                    LogView::new(&args, level).annotate(&mut render);
+                } else if render.content.len() > 102400 {
+                    // Highlights large files here, highlight.js in the browser is used for the small ones
+                    let highlighted = highlighter.highlight(&render.content, &language);
+                    render.set_highlighted(highlighted);
+                }
+                render.set_language(language);
+                if !render.compressed {
+                    if let Some(line) = line_indexes.line_of_offset_if_ready(&path, render.start_seek) {
+                        render.set_start_line(line + 1);
+                    }
+                }
                 return DetailResponse::Template(Template::render("detail", render));
             },
+            Err(e) if jump => {
+                let render = ErrorRender::new(e.to_string());
+                return DetailResponse::Template(Template::render("error", render));
+            },
             Err(_) => {
                 // Download directly
-                return DetailResponse::NamedFile(NamedFile::open(&path).ok());
+                return download_response(&path, &conditions);
             }
         }
     }
@@ -727,23 +5767,28 @@ fn path_to_relative(path: &PathBuf) -> PathBuf {
 }
 
 fn main() {
-    let args: Args = parse_arguments();
+    let app = rocket::ignite();
+    let args: Args = parse_arguments(app.config().workers as usize);
+    let cursor_file = args.cursor_file.clone();
     unsafe {
         GLOBAL_ARGS = Some(args.clone());
     }
-    let app = rocket::ignite()
+    let app = app
         .manage(args)
+        .manage(LineIndexes::new())
+        .manage(Highlighter::new())
+        .manage(ReadCursors::load(&cursor_file))
         .register(catchers![forbidden])
         .mount(
             "/",
-            routes![auth, index, detail, more, search, login, do_login, debug, debug_agent, append, upload, file_exist, delete],
+            routes![auth, index, detail, more, previous, cursor, lines, tail, timeline, timeline_tail, jsonl, jsonl_more, jsonl_tail, hex, preview, diff, search, search_json, search_stream, find, login, do_login, debug, debug_agent, append, upload, file_exist, delete],
         )
         .mount("/public", StaticFiles::from("./templates/static"))
         .attach(Template::fairing());
     app.launch();
 }
 
-fn parse_arguments() -> Args {
+fn parse_arguments(workers: usize) -> Args {
     let matches = App::new("file_reader")
         .version("1.0")
         .author("smoothsea")
@@ -783,6 +5828,65 @@ fn parse_arguments() -> Args {
             .help("写入文件")
             .takes_value(false),
         )
+        .arg(
+            Arg::with_name("time-format")
+                .long("time-format")
+                .help("日志行首的时间格式，如 %Y-%m-%d %H:%M:%S%.f，用于合并多个文件")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("record-start")
+                .long("record-start")
+                .help("多行记录首行的正则，如 ^\\d{4}-，未指定时以时间开头的行为记录首行，用于折叠和搜索堆栈")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("level")
+                .long("level")
+                .help("日志级别及其正则，如 ERROR=\\bE\\d{4}，按严重程度从高到低指定，未指定时识别常见格式")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("cursor-file")
+                .long("cursor-file")
+                .help("保存每个用户阅读位置的文件，默认为当前目录下的 file-reader-cursors.json")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("search-threads")
+                .long("search-threads")
+                .help("同时搜索的文件数，所有的搜索共用，默认为 CPU 核数")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-streams")
+                .long("max-streams")
+                .help("同时实时追踪和流式搜索的连接数上限，每个连接一直占用一个 worker，默认为 Rocket.toml 中 workers 的一半")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("file-type")
+                .long("file-type")
+                .help("指定文件的语言，如 conf/*.cnf=ini，log 表示日志，未指定时按扩展名和内容检测")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("encoding")
+                .short("e")
+                .long("encoding")
+                .help("指定文件编码，如 legacy/*.log=gbk，未指定时自动检测")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+        )
         .get_matches();
 
     let dir = PathBuf::from(matches.value_of("directory").unwrap());
@@ -799,5 +5903,916 @@ fn parse_arguments() -> Args {
 
     let log = matches.is_present("log");
     let write = matches.is_present("write");
-    Args::new(dir, username, password, log, write)
+
+    let mut encodings = vec![];
+    if let Some(values) = matches.values_of("encoding") {
+        for value in values {
+            let (pattern, label) = match value.rfind('=') {
+                Some(pos) => (&value[..pos], &value[pos + 1..]),
+                None => panic!("编码配置错误: {}", value),
+            };
+            let matcher = Glob::new(pattern).expect("编码配置错误").compile_matcher();
+            let encoding = Encoding::for_label(label.as_bytes()).expect("不支持的编码");
+            encodings.push((matcher, encoding));
+        }
+    }
+    let time_formats: Vec<String> = match matches.values_of("time-format") {
+        Some(values) => values.map(|v| v.to_owned()).collect(),
+        None => vec![
+            "%Y-%m-%d %H:%M:%S%.f",
+            "%Y-%m-%dT%H:%M:%S%.f",
+            "%Y-%m-%d %H:%M:%S,%3f",
+            "%Y/%m/%d %H:%M:%S%.f",
+        ]
+        .into_iter()
+        .map(|v| v.to_owned())
+        .collect(),
+    };
+    let mut record_starts = vec![];
+    if let Some(values) = matches.values_of("record-start") {
+        for value in values {
+            record_starts.push(Regex::new(value).expect("记录首行正则错误"));
+        }
+    }
+    let mut file_types = vec![];
+    if let Some(values) = matches.values_of("file-type") {
+        for value in values {
+            let (pattern, language) = match value.rfind('=') {
+                Some(pos) => (&value[..pos], &value[pos + 1..]),
+                None => panic!("文件语言配置错误: {}", value),
+            };
+            let matcher = Glob::new(pattern).expect("文件语言配置错误").compile_matcher();
+            file_types.push((matcher, language.to_lowercase()));
+        }
+    }
+    let levels: Vec<(String, Regex)> = match matches.values_of("level") {
+        Some(values) => values
+            .map(|value| match value.find('=') {
+                Some(pos) => (value[..pos].to_uppercase(), Regex::new(&value[pos + 1..]).expect("日志级别正则错误")),
+                None => panic!("日志级别配置错误: {}", value),
+            })
+            .collect(),
+        None => vec![
+            ("FATAL", r"\b(FATAL|CRITICAL|CRIT|EMERG|ALERT|PANIC)\b|\[(?i:fatal|crit|critical|emerg|alert)\]|(?i:level)\W{1,3}(?i:fatal|critical|panic)\b|PHP Fatal error"),
+            ("ERROR", r"\b(ERROR|ERR|SEVERE)\b|\[(?i:error|err)\]|(?i:level)\W{1,3}(?i:error|err)\b"),
+            ("WARN", r"\b(WARN|WARNING)\b|\[(?i:warn|warning)\]|(?i:level)\W{1,3}(?i:warn|warning)\b|PHP Warning"),
+            ("INFO", r"\b(INFO|NOTICE)\b|\[(?i:info|notice)\]|(?i:level)\W{1,3}(?i:info|notice)\b"),
+            ("DEBUG", r"\bDEBUG\b|\[(?i:debug)\]|(?i:level)\W{1,3}(?i:debug)\b"),
+            ("TRACE", r"\b(TRACE|VERBOSE)\b|\[(?i:trace)\]|(?i:level)\W{1,3}(?i:trace)\b"),
+        ]
+        .into_iter()
+        .map(|(name, pattern)| (name.to_owned(), Regex::new(pattern).unwrap()))
+        .collect(),
+    };
+    let cursor_file = PathBuf::from(matches.value_of("cursor-file").unwrap_or("file-reader-cursors.json"));
+    let search_threads = match matches.value_of("search-threads") {
+        Some(value) => value.parse().expect("搜索线程数错误"),
+        None => num_cpus::get(),
+    };
+    // Half of the workers are kept for the other requests by default
+    let max_streams = match matches.value_of("max-streams") {
+        Some(value) => value.parse().expect("实时追踪连接数错误"),
+        None => workers / 2,
+    };
+    Args::new(
+        dir,
+        username,
+        password,
+        log,
+        write,
+        encodings,
+        time_formats,
+        record_starts,
+        file_types,
+        levels,
+        cursor_file,
+        search_threads,
+        max_streams,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Once;
+
+    static INIT: Once = Once::new();
+
+    // The paths given by the searches are relative to the directory of the tests
+    fn test_dir(name: &str) -> PathBuf {
+        let base = std::env::temp_dir().join(format!("file-reader-test-{}", std::process::id()));
+        INIT.call_once(|| unsafe {
+            GLOBAL_ARGS = Some(Args::new(
+                base.clone(),
+                None,
+                None,
+                false,
+                false,
+                vec![],
+                vec![],
+                vec![],
+                vec![],
+                vec![],
+                base.join("cursors.json"),
+                4,
+                4,
+            ));
+        });
+        let dir = base.join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn search_options(threads: usize) -> SearchOptions {
+        SearchOptions {
+            before: 0,
+            after: 0,
+            case_insensitive: true,
+            fixed_strings: false,
+            word: false,
+            invert: false,
+            boolean: false,
+            max_count: None,
+            mode: SearchMode::Lines,
+            skip_binary: true,
+            threads,
+            slots: Arc::new(Slots::new(threads)),
+            filter: FileFilter::default(),
+        }
+    }
+
+    fn time_parser() -> TimeParser {
+        TimeParser::new(&["%Y-%m-%d %H:%M:%S".to_owned(), "%Y-%m-%d %H:%M:%S%.f".to_owned()])
+    }
+
+    fn time(value: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn parse_prefix_bracketed() {
+        let line = "[2024-01-01 12:00:00] INFO request handled ok, user=42 path=/api";
+        let parser = time_parser();
+        assert_eq!(parser.parse_prefix(line), Some((time("2024-01-01 12:00:00"), 21)));
+        assert_eq!(parser.strip(line), " INFO request handled ok, user=42 path=/api");
+    }
+
+    #[test]
+    fn parse_prefix_leading_space() {
+        let line = "  2024-01-01 12:00:00 started";
+        let parser = time_parser();
+        assert_eq!(parser.parse(line), Some(time("2024-01-01 12:00:00")));
+        assert_eq!(parser.strip(line), " started");
+    }
+
+    #[test]
+    fn parse_prefix_long_line() {
+        let line = format!("2024-01-01 12:00:00 {}", "x".repeat(200));
+        let parser = time_parser();
+        assert_eq!(parser.parse(&line), Some(time("2024-01-01 12:00:00")));
+        assert_eq!(parser.strip(&line).len(), 201);
+    }
+
+    #[test]
+    fn parse_prefix_cjk_line() {
+        let line = format!("[2024-01-01 12:00:00] {}", "请求处理完成".repeat(20));
+        let parser = time_parser();
+        assert_eq!(parser.parse(&line), Some(time("2024-01-01 12:00:00")));
+        assert!(parser.strip(&line).starts_with(" 请求处理完成"));
+        assert_eq!(parser.parse("请求处理完成".repeat(20).as_str()), None);
+    }
+
+    #[test]
+    fn parse_prefix_rfc3339() {
+        let parser = time_parser();
+        let noon = local_time(Utc.ymd(2024, 1, 1).and_hms(12, 0, 0));
+        assert_eq!(parser.parse("2024-01-01T12:00:00Z GET /"), Some(noon));
+        assert_eq!(parser.parse("2024-01-01T20:00:00+08:00 GET /"), Some(noon));
+        assert_eq!(parser.parse("2024-01-01T07:00:00-05:00 GET /"), Some(noon));
+        // A format with an offset is converted the same way
+        let parser = TimeParser::new(&["%Y-%m-%d %H:%M:%S %z".to_owned()]);
+        assert_eq!(parser.parse("2024-01-01 20:00:00 +0800 GET /"), Some(noon));
+    }
+
+    #[test]
+    fn timeline_mixed_zones() {
+        let dir = test_dir("timeline");
+        // The plain timestamps are local times, a minute before and after noon UTC
+        let noon = local_time(Utc.ymd(2024, 1, 1).and_hms(12, 0, 0));
+        let plain = format!(
+            "{} before\n{} after\n",
+            (noon - chrono::Duration::minutes(1)).format("%Y-%m-%d %H:%M:%S"),
+            (noon + chrono::Duration::minutes(1)).format("%Y-%m-%d %H:%M:%S"),
+        );
+        fs::write(dir.join("plain.log"), plain).unwrap();
+        fs::write(dir.join("offset.log"), "2024-01-01T20:00:00+08:00 noon\n").unwrap();
+
+        let paths = vec![dir.join("plain.log"), dir.join("offset.log")];
+        let render = get_timeline_render(&paths, &time_parser()).unwrap();
+        let contents: Vec<&str> = render.entries.iter().filter_map(|e| e.content.split(' ').last()).collect();
+        assert_eq!(contents, vec!["before", "noon", "after"]);
+    }
+
+    #[test]
+    fn parse_age_units() {
+        assert_eq!(parse_age("30m").unwrap(), Duration::from_secs(1800));
+        assert_eq!(parse_age("24").unwrap(), Duration::from_secs(86400));
+        assert_eq!(parse_age("2W").unwrap(), Duration::from_secs(1209600));
+        assert!(parse_age("3y").is_err());
+        assert!(parse_age("d").is_err());
+        assert!(parse_age("99999999999999999w").is_err());
+    }
+
+    #[test]
+    fn parse_size_units() {
+        assert_eq!(parse_size("500").unwrap(), 500);
+        assert_eq!(parse_size("10k").unwrap(), 10240);
+        assert_eq!(parse_size("20MB").unwrap(), 20 << 20);
+        assert_eq!(parse_size("1g").unwrap(), 1 << 30);
+        assert!(parse_size("1t").is_err());
+        assert!(parse_size("99999999999999999g").is_err());
+    }
+
+    #[test]
+    fn parse_offset_radix() {
+        assert_eq!(parse_offset("4096").unwrap(), 4096);
+        assert_eq!(parse_offset(" 0x1f0 ").unwrap(), 0x1f0);
+        assert_eq!(parse_offset("0X00001000").unwrap(), 4096);
+        assert!(parse_offset("0x").is_err());
+        assert!(parse_offset("1f0").is_err());
+    }
+
+    #[test]
+    fn parse_line_span_ranges() {
+        assert_eq!(parse_line_span("12"), Some((12, 12)));
+        assert_eq!(parse_line_span(" 3 - 8 "), Some((3, 8)));
+        assert_eq!(parse_line_span("5-5"), Some((5, 5)));
+        assert_eq!(parse_line_span("0"), None);
+        assert_eq!(parse_line_span("8-3"), None);
+        assert_eq!(parse_line_span("3-"), None);
+        assert_eq!(parse_line_span("a-b"), None);
+    }
+
+    #[test]
+    fn search_truncates_in_file_order() {
+        let dir = test_dir("search_order");
+        for i in 0..40 {
+            fs::write(dir.join(format!("{:02}.log", i)), "error here\n".repeat(500)).unwrap();
+        }
+
+        let mut given = vec![];
+        let progress = search_each_file(&dir, "error", &search_options(4), |file, progress| {
+            given.push((progress.scanned, file.map(|file| file.hits.len())));
+            true
+        })
+        .unwrap();
+
+        assert!(progress.truncated);
+        assert_eq!(progress.matches, 10000);
+        let scanned: Vec<usize> = given.iter().map(|(scanned, _)| *scanned).collect();
+        assert_eq!(scanned, (1..=given.len()).collect::<Vec<usize>>());
+        // Every file before the limit is given in full
+        let counts: Vec<usize> = given.iter().filter_map(|(_, hits)| *hits).collect();
+        assert_eq!(counts, vec![500; 20]);
+    }
+
+    #[test]
+    fn slots_try_acquire_at_cap() {
+        let slots = Arc::new(Slots::new(2));
+        let first = slots.try_acquire().unwrap();
+        let _second = slots.try_acquire().unwrap();
+        assert!(slots.try_acquire().is_none());
+        // A slot is given back when dropped
+        drop(first);
+        assert!(slots.try_acquire().is_some());
+        assert!(Arc::new(Slots::new(0)).try_acquire().is_some());
+    }
+
+    #[test]
+    fn search_stream_events() {
+        let dir = test_dir("search_stream");
+        fs::write(dir.join("a.log"), "ok\nerror one\n").unwrap();
+        let slots = Arc::new(Slots::new(1));
+        let mut stream = SearchStream::start(dir.clone(), "error".to_owned(), search_options(1), &slots).unwrap();
+        // The stream holds the only slot
+        assert!(SearchStream::start(dir.clone(), "error".to_owned(), search_options(1), &slots).is_err());
+
+        let mut output = vec![];
+        let mut buf = [0; 1024];
+        loop {
+            match stream.read(&mut buf) {
+                Ok(0) => break,
+                Ok(len) => output.extend_from_slice(&buf[..len]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(e) => panic!("{}", e),
+            }
+        }
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("event: file\n"));
+        assert!(output.contains("error one"));
+        assert!(output.contains("event: done\n"));
+        drop(stream);
+        assert!(slots.try_acquire().is_some());
+    }
+
+    fn write_bundle(dir: &Path) {
+        let mut builder = tar::Builder::new(File::create(dir.join("bundle.tar")).unwrap());
+        for (name, content) in &[("logs/a.log", "ok\nerror one\n"), ("logs/b.log", "ok\n"), ("c.log", "error two\n")] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, content.as_bytes()).unwrap();
+        }
+        builder.finish().unwrap();
+    }
+
+    #[test]
+    fn search_archive_members() {
+        let dir = test_dir("search_archive");
+        write_bundle(&dir);
+
+        let mut given = vec![];
+        let progress = search_each_file(&dir, "error", &search_options(2), |file, _| {
+            given.extend(file.map(|file| (file.path, file.hits.len())));
+            true
+        })
+        .unwrap();
+
+        assert_eq!(progress.files, 3);
+        assert_eq!(progress.scanned, 3);
+        assert_eq!(
+            given,
+            vec![
+                ("/search_archive/bundle.tar/logs/a.log".to_owned(), 1),
+                ("/search_archive/bundle.tar/c.log".to_owned(), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn search_archive_member_sizes() {
+        let dir = test_dir("search_archive_sizes");
+        write_bundle(&dir);
+
+        let mut options = search_options(2);
+        options.filter.min_size = Some(11);
+        let mut given = vec![];
+        search_each_file(&dir, "error", &options, |file, _| {
+            given.extend(file.map(|file| file.path));
+            true
+        })
+        .unwrap();
+
+        assert_eq!(given, vec!["/search_archive_sizes/bundle.tar/logs/a.log".to_owned()]);
+    }
+
+    #[test]
+    fn search_binary_files() {
+        let dir = test_dir("search_binary");
+        fs::write(dir.join("data.bin"), b"error before\n\x00error after\n").unwrap();
+
+        // Found in a directory, the file is searched up to the NUL byte
+        let mut given = vec![];
+        let progress = search_each_file(&dir, "error", &search_options(1), |file, _| {
+            given.extend(file.map(|file| file.hits.len()));
+            true
+        })
+        .unwrap();
+        assert_eq!(progress.binary, 1);
+        assert_eq!(given, vec![1]);
+
+        // Asked for by its path, it is searched as text
+        let mut given = vec![];
+        search_each_file(&dir.join("data.bin"), "error", &search_options(1), |file, _| {
+            given.extend(file.map(|file| file.hits.len()));
+            true
+        })
+        .unwrap();
+        assert_eq!(given, vec![2]);
+    }
+
+    fn boolean_matcher(search: &str, word: bool) -> BooleanMatcher {
+        let mut options = search_options(1);
+        options.word = word;
+        BooleanMatcher::new(search, &options).unwrap()
+    }
+
+    fn is_match(matcher: &BooleanMatcher, line: &str) -> bool {
+        matcher.is_match(line.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn boolean_matcher_operators() {
+        let matcher = boolean_matcher("timeout AND NOT retry OR panic", false);
+        assert!(is_match(&matcher, "request timeout"));
+        assert!(!is_match(&matcher, "request timeout, retry"));
+        assert!(is_match(&matcher, "thread panic, retry"));
+        assert!(!is_match(&matcher, "all good"));
+    }
+
+    #[test]
+    fn boolean_matcher_spans() {
+        // The first match in the line of any of its patterns
+        let matcher = boolean_matcher("b AND a", false);
+        let m = matcher.find(b"xa b").unwrap().unwrap();
+        assert_eq!((m.start(), m.end()), (1, 2));
+        // A line matched only by NOT patterns is matched as a whole, with nothing to highlight
+        let matcher = boolean_matcher("NOT error", false);
+        let m = matcher.find(b"error\nok").unwrap().unwrap();
+        assert_eq!((m.start(), m.end()), (6, 6));
+    }
+
+    #[test]
+    fn boolean_matcher_words() {
+        let matcher = boolean_matcher("@user AND NOT -v", true);
+        assert!(is_match(&matcher, "ping @user now"));
+        assert!(!is_match(&matcher, "ping x@users now"));
+        assert!(!is_match(&matcher, "ping @user -v"));
+        assert!(is_match(&matcher, "ping @user x-v"));
+    }
+
+    #[test]
+    fn jsonl_filter_conditions() {
+        let filter = JsonFilter::parse("level=ERROR AND status>=500 OR msg~timeout").unwrap();
+        assert!(filter.matches(&JsonRecord::parse(r#"{"level":"error","status":503}"#)));
+        assert!(!filter.matches(&JsonRecord::parse(r#"{"level":"error","status":404}"#)));
+        assert!(filter.matches(&JsonRecord::parse(r#"{"level":"info","msg":"Read Timeout"}"#)));
+        // The lines that aren't JSON objects always pass
+        assert!(filter.matches(&JsonRecord::parse("not json")));
+        assert!(JsonFilter::parse("").unwrap().matches(&JsonRecord::parse(r#"{"level":"info"}"#)));
+    }
+
+    #[test]
+    fn jsonl_filter_fields() {
+        let record = JsonRecord::parse(r#"{"user":{"id":42},"tags":["a","b"],"time":"2024-01-02T00:00:00Z"}"#);
+        assert!(JsonFilter::parse("user.id=42").unwrap().matches(&record));
+        assert!(JsonFilter::parse("tags.1='b'").unwrap().matches(&record));
+        assert!(JsonFilter::parse("user.id<100").unwrap().matches(&record));
+        // Compared as strings when either side isn't a number
+        assert!(JsonFilter::parse("time>2024-01-01").unwrap().matches(&record));
+        // A missing field only passes `!=`
+        assert!(JsonFilter::parse("user.name!=bob").unwrap().matches(&record));
+        assert!(!JsonFilter::parse("user.name=bob").unwrap().matches(&record));
+        assert!(JsonFilter::parse("level").is_err());
+    }
+
+    #[test]
+    fn jsonl_partial_line_seek() {
+        let path = test_dir("jsonl").join("app.jsonl");
+        // The last line is cut in the middle of a character, it is decoded longer than it is
+        fs::write(&path, b"{\"a\":1}\n{\"a\":\"\xe4\xb8").unwrap();
+        let render = get_jsonl_render(&path, 0, None, &JsonFilter::parse("").unwrap()).unwrap();
+        assert_eq!(render.records.len(), 1);
+        assert_eq!(render.seek, 8);
+    }
+
+    fn utf16le(text: &str) -> Vec<u8> {
+        text.encode_utf16().flat_map(|u| u.to_le_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn utf16_line_ends() {
+        // The low byte of 上 (U+4E0A) is 0x0A
+        let buff = utf16le("上\n下\n");
+        assert_eq!(line_ends(&buff, 0, UTF_16LE).collect::<Vec<_>>(), vec![4, 8]);
+        let buff: Vec<u8> = "上\n".encode_utf16().flat_map(|u| u.to_be_bytes().to_vec()).collect();
+        assert_eq!(line_ends(&buff, 0, UTF_16BE).collect::<Vec<_>>(), vec![4]);
+        // Read from an odd offset, the first byte belongs to the newline before
+        assert_eq!(line_ends(&utf16le("\n上\n")[1..], 1, UTF_16LE).collect::<Vec<_>>(), vec![5]);
+
+        let mut reader = io::Cursor::new(utf16le("上上\n下"));
+        let mut line = vec![];
+        assert_eq!(read_line(&mut reader, UTF_16LE, &mut line).unwrap(), 6);
+        assert_eq!(decode_content(&line, UTF_16LE), "上上\n");
+    }
+
+    #[test]
+    fn utf16_line_index() {
+        let path = test_dir("utf16").join("app.log");
+        let mut content = vec![0xFF, 0xFE];
+        content.extend(utf16le("上\n上\n下"));
+        fs::write(&path, &content).unwrap();
+        let line_indexes = LineIndexes::new();
+        let total_lines = line_indexes.with_index(&path, |index, _, file_len| Ok(index.total_lines(file_len))).unwrap();
+        assert_eq!(total_lines, 3);
+        assert_eq!(line_indexes.line_of_offset(&path, 8).unwrap(), 1);
+    }
+
+    #[test]
+    fn gb18030_char_boundary() {
+        let (buff, _, _) = GB18030.encode("中文\n日志");
+        assert_eq!(char_boundary(&buff, 0, GB18030), 0);
+        // Starts at the second byte of 中, 文 is the first whole character
+        assert_eq!(char_boundary(&buff[1..], 1, GB18030), 1);
+        assert_eq!(decode_content(&buff[2..], GB18030), "文\n日志");
+    }
+
+    #[test]
+    fn find_time_offset_with_offsets() {
+        let path = test_dir("jump").join("app.log");
+        let lines = ["2024-01-01T10:00:00+08:00 a", "2024-01-01T10:01:00+08:00 b", "2024-01-01T10:02:00+08:00 c"];
+        fs::write(&path, lines.join("\n")).unwrap();
+        let parser = time_parser();
+
+        let time = parse_jump_time("2024-01-01T02:01:00Z", &path).unwrap();
+        assert_eq!(find_time_offset(&path, time, &parser).unwrap(), 28);
+        // A plain time is a local time like the timestamps of the lines
+        let time = local_time(Utc.ymd(2024, 1, 1).and_hms(2, 1, 30));
+        let time = parse_jump_time(&time.format("%Y-%m-%d %H:%M:%S").to_string(), &path).unwrap();
+        assert_eq!(find_time_offset(&path, time, &parser).unwrap(), 56);
+        // After the last line, the end of the file
+        let time = local_time(Utc.ymd(2024, 1, 1).and_hms(3, 0, 0));
+        assert_eq!(find_time_offset(&path, time, &parser).unwrap(), 83);
+    }
+
+    #[test]
+    fn detail_render_after_rotation() {
+        let dir = test_dir("rotation");
+        let path = dir.join("app.log");
+        fs::write(&path, "old line\n").unwrap();
+        let inode = fs::metadata(&path).unwrap().ino();
+        fs::rename(&path, dir.join("app.log.1")).unwrap();
+        // The new file is longer than a window, it is read from its first line
+        fs::write(&path, format!("first line\n{}", "new line\n".repeat(100000))).unwrap();
+
+        let render = get_detail_render(&path, 9, Some(inode), false).unwrap();
+        assert!(render.rotated);
+        assert_eq!(render.start_seek, 0);
+        assert!(render.content.starts_with("first line\n"));
+        assert!(!render.eof);
+    }
+
+    #[test]
+    fn tail_file_reopen_if_rotated() {
+        let dir = test_dir("tail_rotation");
+        let path = dir.join("app.log");
+        fs::write(&path, "one\n").unwrap();
+        let mut file = TailFile::open(&path, 4, None).unwrap();
+        let inode = file.inode;
+        assert!(file.reopen_if_rotated().unwrap().is_none());
+
+        // The lines written before the rotation are drained from the old file first
+        fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(b"two\n").unwrap();
+        fs::rename(&path, dir.join("app.log.1")).unwrap();
+        fs::write(&path, "three\n").unwrap();
+        match file.reopen_if_rotated().unwrap() {
+            Some(Rotation::Drained(content)) => assert_eq!(content, "two\n"),
+            _ => panic!("the old file isn't drained"),
+        }
+        assert_eq!(file.inode, inode);
+        assert!(matches!(file.reopen_if_rotated().unwrap(), Some(Rotation::Reopened)));
+        assert_eq!((file.seek, file.inode), (0, fs::metadata(&path).unwrap().ino()));
+        assert_eq!(file.read_appended().unwrap().as_deref(), Some("three\n"));
+
+        // Truncated in place
+        fs::write(&path, "").unwrap();
+        assert!(matches!(file.reopen_if_rotated().unwrap(), Some(Rotation::Reopened)));
+        assert_eq!(file.seek, 0);
+    }
+
+    #[test]
+    fn sniff_utf16_with_cjk() {
+        let text = "[2024-01-01 12:00:00] 一切正常，用户登录\n".repeat(20);
+        let le: Vec<u8> = text.encode_utf16().flat_map(|u| u.to_le_bytes().to_vec()).collect();
+        let be: Vec<u8> = text.encode_utf16().flat_map(|u| u.to_be_bytes().to_vec()).collect();
+        assert_eq!(sniff_encoding(&le), UTF_16LE);
+        assert_eq!(sniff_encoding(&be), UTF_16BE);
+        let (gbk, _, _) = GB18030.encode(&text);
+        assert_eq!(sniff_encoding(&gbk), GB18030);
+        assert_eq!(sniff_encoding(text.as_bytes()), UTF_8);
+    }
+
+    #[test]
+    fn previous_render_utf16_lines() {
+        let path = test_dir("previous").join("app.log");
+        let mut content = vec![0xFF, 0xFE];
+        for i in 0..30000 {
+            content.extend(utf16le(&format!("line {:05} 上\n", i)));
+        }
+        fs::write(&path, &content).unwrap();
+
+        // The block starts in the middle of a line, at an odd offset
+        let render = get_previous_render(&path, content.len() as u64 - 27).unwrap();
+        assert_eq!((render.start_seek - 2) % 26, 0);
+        assert!(render.content.starts_with("line "));
+        assert!(render.content.lines().all(|line| line.starts_with("line ")));
+    }
+
+    fn find_offsets(path: &PathBuf, search: &str, target: FindTarget, limit: usize) -> Vec<(u64, u64, u64)> {
+        let render = get_find_render(path, search, target, limit, true, false).unwrap();
+        render.matches.iter().map(|m| (m.index, m.offset, m.length)).collect()
+    }
+
+    #[test]
+    fn find_targets() {
+        let path = test_dir("find").join("app.log");
+        fs::write(&path, "a error\nb\nerror c error\n").unwrap();
+        assert_eq!(find_offsets(&path, "error", FindTarget::From(0), 2), vec![(1, 2, 5), (2, 10, 5)]);
+        assert_eq!(find_offsets(&path, "error", FindTarget::From(3), 1), vec![(2, 10, 5)]);
+        assert_eq!(find_offsets(&path, "error", FindTarget::Nth(3), 5), vec![(3, 18, 5)]);
+        // The nearest matches before the offset
+        assert_eq!(find_offsets(&path, "error", FindTarget::Before(18), 1), vec![(2, 10, 5)]);
+        assert_eq!(find_offsets(&path, "error", FindTarget::Before(18), 5), vec![(1, 2, 5), (2, 10, 5)]);
+        let render = get_find_render(&path, "error", FindTarget::From(0), 1, true, true).unwrap();
+        assert_eq!((render.matches.len(), render.total), (1, Some(3)));
+    }
+
+    #[test]
+    fn find_decoded_offsets() {
+        let dir = test_dir("find_decoded");
+        // The offsets and lengths are of the bytes in the file
+        let path = dir.join("gbk.log");
+        let (gbk, _, _) = GB18030.encode("[x] 错误一\n正常\n错误二\n");
+        fs::write(&path, &gbk).unwrap();
+        assert_eq!(detect_encoding(&path), GB18030);
+        assert_eq!(find_offsets(&path, "错误", FindTarget::From(0), 5), vec![(1, 4, 4), (2, 16, 4)]);
+
+        let path = dir.join("utf16.log");
+        let mut content = vec![0xFF, 0xFE];
+        content.extend(utf16le("ok\n错误\n"));
+        fs::write(&path, &content).unwrap();
+        assert_eq!(find_offsets(&path, "错误", FindTarget::From(0), 5), vec![(1, 8, 4)]);
+        assert_eq!(find_offsets(&path, "错误", FindTarget::Before(8), 5), vec![]);
+    }
+
+    #[test]
+    fn line_index_sparse_stride() {
+        let path = test_dir("line_index").join("app.log");
+        let lines: Vec<String> = (0..3000).map(|i| format!("line {}{}\n", i, "x".repeat(i % 7))).collect();
+        fs::write(&path, lines.concat()).unwrap();
+        let starts: Vec<u64> = lines.iter().scan(0, |offset, line| {
+            let start = *offset;
+            *offset += line.len() as u64;
+            Some(start)
+        }).collect();
+
+        let mut file = File::open(&path).unwrap();
+        let file_len = file.metadata().unwrap().len();
+        let mut index = LineIndex::new(file.metadata().unwrap().ino(), UTF_8);
+        index.update(&mut file, file_len).unwrap();
+        assert_eq!(index.total_lines(file_len), 3000);
+        assert_eq!(index.offsets, vec![0, starts[1024], starts[2048]]);
+        for line in &[0, 1, 1023, 1024, 1025, 2047, 2048, 2999] {
+            assert_eq!(index.offset_of_line(&mut file, *line).unwrap(), starts[*line as usize]);
+            assert_eq!(index.line_of_offset(&mut file, starts[*line as usize] + 2).unwrap(), *line);
+        }
+
+        // The appended lines are indexed from where the index stopped, an incomplete line isn't counted
+        fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(&b"more\n".repeat(100)).unwrap();
+        fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(b"half").unwrap();
+        let file_len = file.metadata().unwrap().len();
+        index.update(&mut file, file_len).unwrap();
+        assert_eq!(index.lines, 3100);
+        assert_eq!(index.total_lines(file_len), 3101);
+        assert_eq!(index.offsets.len(), 4);
+    }
+
+    #[test]
+    fn line_indexes_in_background() {
+        let path = test_dir("line_index_background").join("app.log");
+        fs::write(&path, "0123456789abcde\n".repeat(LINE_INDEX_INLINE_LEN as usize / 16 + 1024)).unwrap();
+        let line_indexes = LineIndexes::new();
+        // Too large to index on the request, only one thread indexes it
+        assert!(line_indexes.line_of_offset(&path, 16).is_err());
+        assert_eq!(line_indexes.line_of_offset_if_ready(&path, 16), None);
+        assert!(line_indexes.indexing.lock().unwrap().len() <= 1);
+
+        let started = Instant::now();
+        while !line_indexes.indexing.lock().unwrap().is_empty() && started.elapsed() < Duration::from_secs(30) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(line_indexes.line_of_offset_if_ready(&path, 16), Some(1));
+        assert_eq!(line_indexes.line_of_offset(&path, 16 * 2000).unwrap(), 2000);
+    }
+
+    #[test]
+    fn diff_range_parse() {
+        assert!(matches!(DiffRange::parse(" ").unwrap(), DiffRange::Whole));
+        assert!(matches!(DiffRange::parse("10-200").unwrap(), DiffRange::Lines(10, 200)));
+        assert!(matches!(DiffRange::parse("lines:5-5").unwrap(), DiffRange::Lines(5, 5)));
+        assert!(matches!(DiffRange::parse("bytes:0-4096").unwrap(), DiffRange::Bytes(0, 4096)));
+        assert_eq!(DiffRange::parse("lines:0-10").unwrap_err().to_string(), "行号从 1 开始：lines:0-10");
+        assert_eq!(DiffRange::parse("lines:20-10").unwrap_err().to_string(), "范围的起点大于终点：lines:20-10");
+        assert_eq!(DiffRange::parse("bytes:4096-0").unwrap_err().to_string(), "范围的起点大于终点：bytes:4096-0");
+        assert!(DiffRange::parse("pages:1-2").is_err());
+        assert!(DiffRange::parse("lines:10").is_err());
+    }
+
+    #[test]
+    fn read_cursors_round_trip() {
+        let file = test_dir("read_cursors").join("cursors.json");
+        let cursors = ReadCursors::load(&file);
+        assert!(cursors.get("user:admin", "logs/app.log").is_none());
+        cursors.update("user:admin", "logs/app.log", ReadCursor { inode: 1, offset: 100 });
+        // A cursor only moves forward, unless the file has been rotated
+        cursors.update("user:admin", "logs/app.log", ReadCursor { inode: 1, offset: 50 });
+        assert_eq!(cursors.get("user:admin", "logs/app.log").unwrap().offset, 100);
+        cursors.update("browser:1f", "logs/app.log", ReadCursor { inode: 2, offset: 10 });
+        cursors.update("browser:1f", "logs/app.log", ReadCursor { inode: 3, offset: 5 });
+        save_cursors(&file, &cursors.cursors).unwrap();
+
+        let loaded = ReadCursors::load(&file);
+        let cursor = loaded.get("user:admin", "logs/app.log").unwrap();
+        assert_eq!((cursor.inode, cursor.offset), (1, 100));
+        let cursor = loaded.get("browser:1f", "logs/app.log").unwrap();
+        assert_eq!((cursor.inode, cursor.offset), (3, 5));
+        assert!(loaded.get("user:other", "logs/app.log").is_none());
+    }
+
+    fn conditions(range: Option<&str>, if_range: Option<&str>, if_none_match: Option<&str>, if_modified_since: Option<&str>) -> RequestConditions {
+        RequestConditions {
+            range: range.map(|value| value.to_owned()),
+            if_range: if_range.map(|value| value.to_owned()),
+            if_none_match: if_none_match.map(|value| value.to_owned()),
+            if_modified_since: if_modified_since.map(|value| value.to_owned()),
+        }
+    }
+
+    #[test]
+    fn request_byte_range() {
+        let modified = "Mon, 01 Jan 2024 12:00:00 GMT";
+        let byte_range = |range, if_range| conditions(Some(range), if_range, None, None).byte_range(1000, "\"abc\"", modified);
+        assert_eq!(conditions(None, None, None, None).byte_range(1000, "\"abc\"", modified), ByteRange::Whole);
+        assert_eq!(byte_range("bytes=0-99", None), ByteRange::Part(0, 99));
+        assert_eq!(byte_range("bytes=900-", None), ByteRange::Part(900, 999));
+        assert_eq!(byte_range("bytes=900-2000", None), ByteRange::Part(900, 999));
+        assert_eq!(byte_range("bytes=-100", None), ByteRange::Part(900, 999));
+        assert_eq!(byte_range("bytes=1000-", None), ByteRange::Unsatisfiable);
+        // Ranges that can't be served as one part are answered with the whole file
+        assert_eq!(byte_range("bytes=0-9,20-29", None), ByteRange::Whole);
+        assert_eq!(byte_range("bytes=99-0", None), ByteRange::Whole);
+        assert_eq!(byte_range("lines=0-9", None), ByteRange::Whole);
+        // Only a copy that is still valid is continued
+        assert_eq!(byte_range("bytes=0-99", Some("\"abc\"")), ByteRange::Part(0, 99));
+        assert_eq!(byte_range("bytes=0-99", Some(modified)), ByteRange::Part(0, 99));
+        assert_eq!(byte_range("bytes=0-99", Some("\"old\"")), ByteRange::Whole);
+    }
+
+    #[test]
+    fn request_not_modified() {
+        let modified = Utc.ymd(2024, 1, 1).and_hms(12, 0, 0);
+        let not_modified = |if_none_match, if_modified_since| {
+            conditions(None, None, if_none_match, if_modified_since).not_modified("\"abc\"", Some(&modified))
+        };
+        assert!(!not_modified(None, None));
+        assert!(not_modified(Some("\"abc\""), None));
+        assert!(not_modified(Some("\"old\", W/\"abc\""), None));
+        assert!(not_modified(Some("*"), None));
+        assert!(not_modified(None, Some("Mon, 01 Jan 2024 12:00:00 GMT")));
+        assert!(!not_modified(None, Some("Mon, 01 Jan 2024 11:59:59 GMT")));
+        assert!(!not_modified(None, Some("yesterday")));
+        // The entity tag is checked instead of the time when both are given
+        assert!(!not_modified(Some("\"old\""), Some("Mon, 01 Jan 2024 12:00:00 GMT")));
+        assert!(!conditions(None, None, None, Some("Mon, 01 Jan 2024 12:00:00 GMT")).not_modified("\"abc\"", None));
+    }
+
+    fn level_classifier() -> LevelClassifier {
+        LevelClassifier {
+            levels: vec![("ERROR", r"\bERROR\b"), ("WARN", r"\bWARN\b"), ("INFO", r"\bINFO\b")]
+                .into_iter()
+                .map(|(name, pattern)| (name.to_owned(), Regex::new(pattern).unwrap()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn level_annotate() {
+        let classifier = level_classifier();
+        let content = "started\n2024-01-01 INFO ready\n2024-01-01 ERROR failed\n  at main\n2024-01-01 WARN slow, not an ERROR\n";
+        let mut render = DetailRender::new(content.to_owned(), "app.log".to_owned(), 0);
+        classifier.annotate(&mut render, None);
+        assert_eq!(render.content, content);
+        assert_eq!(render.level_names, vec!["ERROR", "WARN", "INFO"]);
+        assert_eq!(render.level_counts, vec![1, 1, 1]);
+        // The stack trace takes the level of its record, the first level in a line counts
+        assert_eq!(render.line_levels, vec!["", "INFO", "ERROR", "ERROR", "WARN"]);
+
+        let mut render = DetailRender::new(content.to_owned(), "app.log".to_owned(), 0);
+        classifier.annotate(&mut render, classifier.index_of(" warn"));
+        assert_eq!(render.content, "2024-01-01 ERROR failed\n  at main\n2024-01-01 WARN slow, not an ERROR\n");
+        assert_eq!(render.level_counts, vec![1, 1, 1]);
+        assert_eq!(render.line_levels, vec!["ERROR", "ERROR", "WARN"]);
+    }
+
+    #[test]
+    fn record_splitter_lines() {
+        let splitter = RecordSplitter { patterns: vec![], time_parser: time_parser() };
+        let content = "header\n2024-01-01 10:00:00 ERROR failed\n  at a\n  at b\n2024-01-01 10:00:01 INFO done\n";
+        assert_eq!(splitter.record_lines(content), vec![1, 3, 1]);
+        // Nothing when every record is a single line
+        assert_eq!(splitter.record_lines("2024-01-01 10:00:00 a\n2024-01-01 10:00:01 b\n"), Vec::<usize>::new());
+
+        let splitter = RecordSplitter { patterns: vec![Regex::new(r"^\[").unwrap()], time_parser: time_parser() };
+        assert_eq!(splitter.record_lines("[a] one\nmore\n[b] two\n2024-01-01 10:00:00 three\n"), vec![0, 2, 2]);
+    }
+
+    #[test]
+    fn detect_languages() {
+        let dir = test_dir("languages");
+        let mut args = unsafe { GLOBAL_ARGS.clone().unwrap() };
+        args.file_types = vec![(Glob::new("languages/*.conf").unwrap().compile_matcher(), "ini".to_owned())];
+        let language = |name: &str, sample: &str| detect_language_with(&dir.join(name), &args, sample.as_bytes());
+
+        // The configured types go first, then the extension, then the first line
+        assert_eq!(language("nginx.conf", "server {"), "ini");
+        assert_eq!(language("main.RS", ""), "rust");
+        assert_eq!(language("app.js.gz", ""), "javascript");
+        assert_eq!(language("README.md", "#!/bin/sh"), "markdown");
+        assert_eq!(language("deploy", "#!/usr/bin/env python3\nimport os"), "python");
+        assert_eq!(language("start", "\u{feff}#!/bin/bash\n"), "bash");
+        assert_eq!(language("run", "#!/opt/unknown\n"), "bash");
+        assert_eq!(language("index", "<?PHP echo 1;"), "php");
+        assert_eq!(language("feed", "<?xml version=\"1.0\"?>"), "xml");
+        assert_eq!(language("page", "<!DOCTYPE html>"), "html");
+        assert_eq!(language("app.log.1", "2024-01-01 INFO started"), "log");
+    }
+
+    #[test]
+    fn preview_render() {
+        let dir = test_dir("preview");
+        fs::write(dir.join("runbook.md"), "# Restart\n\n| a | b |\n|---|---|\n| 1 | 2 |\n\n<script>alert(1)</script>\n").unwrap();
+        let render = get_preview_render(&dir.join("runbook.md"), "markdown", 1, None, false).unwrap();
+        assert!(render.markdown && render.html.contains("<h1>Restart</h1>") && render.html.contains("<table>"));
+        assert!(!render.html.contains("<script>"));
+
+        fs::write(dir.join("config.yml"), "name: app\nports: [80, 443]\nnote: </script>\n").unwrap();
+        let render = get_preview_render(&dir.join("config.yml"), "yaml", 1, None, false).unwrap();
+        assert_eq!(render.json, r#"{"name":"app","note":"<\/script>","ports":[80,443]}"#);
+        assert!(get_preview_render(&dir.join("config.yml"), "json", 1, None, false).is_err());
+
+        // Sorted by the numbers in a column, then cut into pages of 200 rows
+        let rows: Vec<String> = (0..250).map(|i| format!("host{};{}\n", i, i % 50)).collect();
+        fs::write(dir.join("hosts.csv"), format!("name;count\n{}", rows.concat())).unwrap();
+        let render = get_preview_render(&dir.join("hosts.csv"), "csv", 2, Some(1), true).unwrap();
+        assert_eq!(render.header, vec!["name", "count"]);
+        assert_eq!((render.total_rows, render.page, render.pages), (250, 2, 2));
+        assert_eq!(render.rows.len(), 50);
+        assert!(render.rows.iter().all(|row| row[1].parse::<usize>().unwrap() < 10));
+        let render = get_preview_render(&dir.join("hosts.csv"), "csv", 9, Some(1), false).unwrap();
+        assert_eq!(render.page, 2);
+        assert_eq!(render.rows.last().unwrap()[1], "49");
+
+        // Without a header row the columns are numbered
+        fs::write(dir.join("data.tsv"), "1\ta\n2\tb\n").unwrap();
+        let render = get_preview_render(&dir.join("data.tsv"), "tsv", 1, None, false).unwrap();
+        assert_eq!(render.header, vec!["第1列", "第2列"]);
+        assert_eq!(render.rows, vec![vec!["1", "a"], vec!["2", "b"]]);
+    }
+
+    #[test]
+    fn compressed_detail_and_search() {
+        let dir = test_dir("compressed");
+        let lines: Vec<String> = (0..20000).map(|i| format!("2024-01-01 10:00:00 line {}\n", i)).collect();
+        let content = lines.concat();
+        let mut encoder = flate2::write::GzEncoder::new(File::create(dir.join("app.log.1.gz")).unwrap(), flate2::Compression::default());
+        encoder.write_all(content.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+        let path = dir.join("app.log.1.gz");
+
+        // The last window of the decompressed content, like the end of a plain file
+        let render = get_detail_render(&path, 0, None, false).unwrap();
+        assert!(render.compressed);
+        assert_eq!(render.seek, content.len() as u64);
+        assert_eq!(render.start_seek, content.len() as u64 - 512000);
+        assert_eq!(&content[render.start_seek as usize..], render.content);
+
+        // Paging from a seek reads the next window only
+        let render = get_detail_render(&path, 10, None, false).unwrap();
+        assert_eq!(render.start_seek, 10);
+        assert!(!render.eof);
+        assert!(render.content.ends_with('\n'));
+        assert_eq!(&content[10..render.seek as usize], render.content);
+
+        let render = get_search_json_render(&path, "line 12345$", &search_options(1)).unwrap();
+        assert_eq!(render.files.len(), 1);
+        assert_eq!(render.files[0].hits.len(), 1);
+        assert_eq!(render.files[0].hits[0].line, 12346);
+    }
+
+    #[test]
+    fn search_json_offsets_and_spans() {
+        let dir = test_dir("search_json");
+        fs::write(dir.join("app.log"), "start\nan error here\nok\nerror and error\nend\n").unwrap();
+        let mut options = search_options(1);
+        options.before = 1;
+        options.after = 1;
+        let render = get_search_json_render(&dir.join("app.log"), "error", &options).unwrap();
+        assert_eq!(render.search, "error");
+        assert!(!render.truncated);
+        assert_eq!(render.files.len(), 1);
+        let hits = &render.files[0].hits;
+        assert_eq!(hits.len(), 2);
+
+        assert_eq!((hits[0].line, hits[0].offset), (2, Some(6)));
+        assert_eq!(hits[0].text, "an error here");
+        assert_eq!(hits[0].spans, vec![(3, 8)]);
+        assert_eq!(hits[0].before.iter().map(|l| (l.line, l.offset, l.text.as_str())).collect::<Vec<_>>(), vec![(1, Some(0), "start")]);
+        assert_eq!(hits[0].after.iter().map(|l| (l.line, l.offset, l.text.as_str())).collect::<Vec<_>>(), vec![(3, Some(20), "ok")]);
+
+        assert_eq!((hits[1].line, hits[1].offset), (4, Some(23)));
+        assert_eq!(hits[1].spans, vec![(0, 5), (10, 15)]);
+        assert_eq!(hits[1].after.iter().map(|l| l.text.as_str()).collect::<Vec<_>>(), vec!["end"]);
+    }
 }